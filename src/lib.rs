@@ -0,0 +1,4074 @@
+//! The core of docker-pullator: the config format, fetching tags from Docker Hub and other
+//! registries, and pulling, pushing and cleaning the configured images through docker.
+//!
+//! The `docker-pullator` binary is a thin CLI over this crate, e.g. to pull every configured tag:
+//!
+//! ```no_run
+//! # async fn example() -> anyhow::Result<()> {
+//! use docker_pullator::{pull, read_config, Docker, DockerOutput, Hub, PullOptions, Report};
+//!
+//! let config = read_config("config.json")?;
+//! let hub = Hub::new(docker_pullator::DEFAULT_HUB_URL, std::time::Duration::from_secs(30))?;
+//! let docker = Docker::new("docker".to_string(), DockerOutput::Tee);
+//!
+//! pull(&config, &hub, &docker, &PullOptions::default(), &mut Report::default()).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    io::IsTerminal,
+    path::PathBuf,
+    process::{ExitStatus, Output, Stdio},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use clap::{Parser, ValueEnum};
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    process::{Child, Command},
+    sync::Semaphore,
+};
+
+/// How many tags [`fetch_tags`] fetches.
+#[derive(Parser, Default, Clone, Copy)]
+pub struct FetchOptions {
+    /// The maximum number of tag pages to fetch from Docker Hub
+    #[clap(long)]
+    pub max_pages: Option<usize>,
+
+    /// Stop fetching once this many tags are known
+    #[clap(long)]
+    pub max_tags: Option<usize>,
+
+    /// The number of tags requested per page, at most 100 [default: 100]
+    #[clap(long)]
+    pub page_size: Option<usize>,
+
+    /// Keep the tags fetched so far when a later page fails instead of giving up
+    #[clap(long)]
+    pub partial_ok: bool,
+}
+
+/// An image reference like `ghcr.io/org/app:1.0`, split into the parts of a profile.
+#[derive(Debug, Clone)]
+pub struct ImageReference {
+    pub registry: Option<String>,
+    pub library: Option<String>,
+    pub repo: String,
+    pub tag: Option<String>,
+}
+
+/// Parses `[registry/][library/]repo[:tag]`, leaving official Docker Hub images without a library.
+pub fn parse_image_reference(value: &str) -> Result<ImageReference, String> {
+    if value.contains('@') {
+        return Err(format!(
+            "digest reference {value:?} is not supported, use name[:tag]"
+        ));
+    }
+
+    let (name, tag) = match value.rsplit_once(':') {
+        // A colon before the last slash belongs to the registry port.
+        Some((name, tag)) if !tag.contains('/') => (name, Some(tag)),
+        _ => (value, None),
+    };
+    if tag.is_some_and(str::is_empty) {
+        return Err(format!("empty tag in {value:?}"));
+    }
+
+    let mut parts = name.split('/').collect::<Vec<_>>();
+    let registry = (parts.len() > 1 && (parts[0].contains(['.', ':']) || parts[0] == "localhost"))
+        .then(|| parts.remove(0))
+        .filter(|registry| {
+            !matches!(
+                TagSource::from_registry(Some(registry)),
+                TagSource::DockerHub
+            )
+        });
+
+    let repo = parts
+        .pop()
+        .filter(|repo| !repo.is_empty())
+        .ok_or_else(|| format!("missing repository in {value:?}"))?;
+    // Official images like nginx live in the library namespace, same as add --repo nginx.
+    let library = (!parts.is_empty())
+        .then(|| parts.join("/"))
+        .filter(|library| registry.is_some() || library != "library");
+
+    Ok(ImageReference {
+        registry: registry.map(String::from),
+        library,
+        repo: repo.to_string(),
+        tag: tag.map(String::from),
+    })
+}
+
+impl FetchOptions {
+    /// Docker Hub rejects page sizes above 100.
+    const MAX_PAGE_SIZE: usize = 100;
+
+    fn page_size(&self) -> usize {
+        self.page_size
+            .unwrap_or(Self::MAX_PAGE_SIZE)
+            .clamp(1, Self::MAX_PAGE_SIZE)
+    }
+
+    fn reached(&self, pages: usize, tags: usize) -> bool {
+        self.max_pages.is_some_and(|max| pages >= max)
+            || self.max_tags.is_some_and(|max| tags >= max)
+    }
+
+    /// Decides whether a failed page ends the fetch with the tags gathered so far.
+    fn keep_partial(&self, pages: usize, image: &str, error: &PullatorError) -> bool {
+        if !self.partial_ok || pages == 0 {
+            return false;
+        }
+
+        tracing::warn!("Failed to fetch page {} of tags for {image}, keeping the tags fetched so far: {error:#}", pages + 1);
+        true
+    }
+
+    /// Drops the tags beyond --max-tags, returning whether any were dropped.
+    fn truncate(&self, results: &mut Vec<FetchTagsItem>) -> bool {
+        match self.max_tags {
+            Some(max) if results.len() > max => {
+                results.truncate(max);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// How [`pull`] pulls the configured tags.
+#[derive(Parser, Clone)]
+pub struct PullOptions {
+    /// The number of images to pull at the same time
+    #[clap(long, default_value_t = 3)]
+    pub concurrency: usize,
+
+    /// How often to retry a failed pull
+    #[clap(long, default_value_t = 3)]
+    pub retries: u32,
+
+    /// The delay before the first retry, doubled after every further attempt
+    #[clap(long, default_value = "1s", value_parser = humantime::parse_duration)]
+    pub retry_delay: Duration,
+
+    /// The platform to pull for profiles without their own platforms, e.g. linux/amd64
+    #[clap(long, value_parser = parse_platform)]
+    pub platform: Option<String>,
+
+    /// A registry mirror to pull from instead of Docker Hub, e.g. mirror.corp
+    #[clap(long)]
+    pub mirror: Option<String>,
+
+    /// Skip tags that are already present locally
+    #[clap(long)]
+    pub if_missing: bool,
+
+    /// Skip tags whose local digest matches the digest on Docker Hub
+    #[clap(long)]
+    pub check_digest: bool,
+
+    /// Always run docker pull, even when --if-missing or --check-digest would skip the tag
+    #[clap(long, visible_alias = "force-pull")]
+    pub force: bool,
+
+    /// Only accept signed images, pulling with Docker Content Trust (DOCKER_CONTENT_TRUST=1)
+    #[clap(long)]
+    pub verify_signatures: bool,
+}
+
+/// The same defaults as the command line flags.
+impl Default for PullOptions {
+    fn default() -> Self {
+        Self {
+            concurrency: 3,
+            retries: 3,
+            retry_delay: Duration::from_secs(1),
+            platform: None,
+            mirror: None,
+            if_missing: false,
+            check_digest: false,
+            force: false,
+            verify_signatures: false,
+        }
+    }
+}
+
+/// What happens with the output of the docker commands.
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum DockerOutput {
+    /// Show the output live and keep the error output for failure messages
+    Tee,
+    /// Pass the output straight through to the terminal
+    Inherit,
+    /// Hide the output and only show errors in failure messages
+    Capture,
+}
+
+/// How the progress of docker pull is shown.
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum Progress {
+    /// Show the docker output as set by --docker-output
+    Plain,
+    /// Draw a progress bar of the layers per pulled tag, plain without a terminal
+    Tui,
+}
+
+/// Reads and migrates the config at `path`, JSON or YAML by its extension, or an empty
+/// config if there is none yet. A `path` of `-` reads JSON or YAML from stdin.
+pub fn read_config(path: &str) -> anyhow::Result<Config> {
+    if path == "-" {
+        let mut content = String::new();
+        std::io::Read::read_to_string(&mut std::io::stdin(), &mut content)
+            .context("Failed to read config from stdin")?;
+        // Without an extension, JSON is told apart by its opening brace.
+        let value: serde_json::Value = if content.trim_start().starts_with('{') {
+            serde_json::from_str(&content).context("Failed to parse config")?
+        } else {
+            serde_yaml::from_str(&content).context("Failed to parse config")?
+        };
+        return serde_json::from_value(migrate_config(value)?).context("Failed to parse config");
+    }
+
+    let path = std::path::Path::new(path);
+    if !path.exists() {
+        return Ok(Default::default());
+    }
+
+    let content = std::fs::read_to_string(path).context("Failed to read config")?;
+    let value: serde_json::Value = match ConfigFormat::from_path(path) {
+        ConfigFormat::Json => serde_json::from_str(&content).context("Failed to parse config")?,
+        ConfigFormat::Yaml => serde_yaml::from_str(&content).context("Failed to parse config")?,
+    };
+
+    serde_json::from_value(migrate_config(value)?).context("Failed to parse config")
+}
+
+/// Reads a standalone policy file, JSON or YAML by its extension.
+pub fn read_policy(path: &str) -> anyhow::Result<Policy> {
+    let path = std::path::Path::new(path);
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read policy {}", path.display()))?;
+    match ConfigFormat::from_path(path) {
+        ConfigFormat::Json => serde_json::from_str(&content).context("Failed to parse policy"),
+        ConfigFormat::Yaml => serde_yaml::from_str(&content).context("Failed to parse policy"),
+    }
+}
+
+const CONFIG_VERSION: u32 = 2;
+
+fn first_config_version() -> u32 {
+    1
+}
+
+fn migrate_config(mut value: serde_json::Value) -> anyhow::Result<serde_json::Value> {
+    let config = value
+        .as_object_mut()
+        .context("Failed to parse config: expected an object")?;
+
+    let version = match config.get("version") {
+        None => first_config_version(),
+        Some(version) => version
+            .as_u64()
+            .and_then(|version| u32::try_from(version).ok())
+            .context("Failed to parse config: version must be a number")?,
+    };
+
+    if version > CONFIG_VERSION {
+        anyhow::bail!(
+            "Config version {version} is newer than the supported version {CONFIG_VERSION}, please upgrade docker-pullator"
+        );
+    }
+
+    for version in version..CONFIG_VERSION {
+        match version {
+            // Version 1 configs predate the registry, platform and digest fields,
+            // which all fall back to their defaults, so only the version changes.
+            1 => {}
+            _ => unreachable!("no migration from config version {version}"),
+        }
+    }
+
+    config.insert("version".to_string(), CONFIG_VERSION.into());
+
+    Ok(value)
+}
+
+/// Writes the config to `path`, keeping the previous content as `{path}.bak` and the last
+/// `backups` versions in [`config_backups`].
+pub fn write_config(path: &str, config: &Config, backups: usize) -> anyhow::Result<()> {
+    let content = match ConfigFormat::from_path(std::path::Path::new(path)) {
+        ConfigFormat::Json => {
+            serde_json::to_string_pretty(config).context("Failed to serialize config")?
+        }
+        ConfigFormat::Yaml => {
+            serde_yaml::to_string(config).context("Failed to serialize config")?
+        }
+    };
+
+    if std::path::Path::new(path).exists() {
+        std::fs::copy(path, format!("{path}.bak")).context("Failed to back up config")?;
+        rotate_backups(path, backups).context("Failed to back up config")?;
+    }
+
+    write_atomically(path, content.as_bytes()).context("Failed to write config")
+}
+
+/// The directory next to the config holding its timestamped backups.
+fn backups_dir(path: &str) -> std::path::PathBuf {
+    let path = std::path::Path::new(path);
+    path.parent()
+        .unwrap_or(std::path::Path::new(""))
+        .join(".backups")
+}
+
+/// The file name prefix and suffix of the backups, which keep the extension so the
+/// format is still recognized, e.g. `config.20240101T120000.000Z.json`.
+fn backup_affixes(path: &str) -> (String, String) {
+    let path = std::path::Path::new(path);
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let extension = path
+        .extension()
+        .map(|extension| format!(".{}", extension.to_string_lossy()))
+        .unwrap_or_default();
+
+    (format!("{stem}."), extension)
+}
+
+/// The timestamped backups of the config kept by [`write_config`], newest first.
+pub fn config_backups(path: &str) -> anyhow::Result<Vec<std::path::PathBuf>> {
+    let dir = backups_dir(path);
+    if !dir.exists() {
+        return Ok(vec![]);
+    }
+
+    let (prefix, suffix) = backup_affixes(path);
+    let mut backups = std::fs::read_dir(&dir)
+        .with_context(|| format!("Failed to read {}", dir.display()))?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|backup| {
+            backup
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.strip_prefix(&prefix)?.strip_suffix(&suffix))
+                // The timestamp, so backups of config.json skip config.yaml and config.local.json.
+                .is_some_and(|stamp| stamp.len() == 20 && stamp.ends_with('Z'))
+        })
+        .collect::<Vec<_>>();
+
+    // The timestamps sort chronologically.
+    backups.sort();
+    backups.reverse();
+    Ok(backups)
+}
+
+/// Copies the config into a new timestamped backup and removes all but the newest `keep`.
+fn rotate_backups(path: &str, keep: usize) -> anyhow::Result<()> {
+    if keep == 0 {
+        return Ok(());
+    }
+
+    let dir = backups_dir(path);
+    std::fs::create_dir_all(&dir)?;
+
+    let stamp = humantime::format_rfc3339_millis(SystemTime::now())
+        .to_string()
+        .replace(['-', ':'], "");
+    let (prefix, suffix) = backup_affixes(path);
+    std::fs::copy(path, dir.join(format!("{prefix}{stamp}{suffix}")))?;
+
+    for backup in config_backups(path)?.into_iter().skip(keep) {
+        std::fs::remove_file(&backup)
+            .with_context(|| format!("Failed to remove {}", backup.display()))?;
+    }
+
+    Ok(())
+}
+
+/// Writes next to the target and renames over it, so readers never see a truncated file.
+fn write_atomically(path: &str, content: &[u8]) -> std::io::Result<()> {
+    let temp = format!("{path}.tmp");
+    let result = (|| {
+        let mut file = std::fs::File::create(&temp)?;
+        std::io::Write::write_all(&mut file, content)?;
+        file.sync_all()
+    })();
+
+    if let Err(error) = result {
+        let _ = std::fs::remove_file(&temp);
+        return Err(error);
+    }
+
+    std::fs::rename(&temp, path)
+}
+
+/// Compares the configured tags with the local images, like a sync would before pulling.
+pub async fn plan(config: &Config, hub: &Hub, docker: &Docker) -> anyhow::Result<Vec<PlanEntry>> {
+    // Tags pulled through @latest directives are configured like any other.
+    let config = &resolve_latest(config, hub).await?;
+    let token = hub_token(hub, config.auth.as_ref()).await?;
+    let mut changes = vec![];
+    let mut configured = BTreeMap::<String, BTreeSet<&str>>::new();
+
+    for profile in config.pull_profiles.values() {
+        let image = profile.image();
+        configured
+            .entry(image.clone())
+            .or_default()
+            .extend(profile.active_tags().map(String::as_str));
+
+        let response = fetch_tags(
+            hub,
+            profile.registry.as_deref(),
+            profile.library.as_ref(),
+            &profile.repo,
+            FetchOptions::default(),
+            token.as_deref(),
+        )
+        .await?;
+
+        for tag in profile.active_tags() {
+            let expected = profile.pinned_digest(tag).cloned().or_else(|| {
+                response
+                    .results
+                    .iter()
+                    .find(|item| &item.name == tag)
+                    .and_then(|item| item.digest.clone())
+            });
+
+            let change = match local_digests(docker, &profile.reference(tag)).await? {
+                None => PlanChange::Pull,
+                Some(local) if expected.is_some_and(|digest| !has_digest(&local, &digest)) => {
+                    PlanChange::Update
+                }
+                Some(_) => continue,
+            };
+
+            changes.push(PlanEntry {
+                image: image.clone(),
+                tag: tag.clone(),
+                change,
+            });
+        }
+    }
+
+    for (image, tags) in &configured {
+        for tag in local_tags(docker, image).await? {
+            if !tags.contains(tag.as_str()) {
+                changes.push(PlanEntry {
+                    image: image.clone(),
+                    tag,
+                    change: PlanChange::Unconfigured,
+                });
+            }
+        }
+    }
+
+    Ok(changes)
+}
+
+/// A change [`plan`] found for one tag.
+#[derive(Debug, Serialize)]
+pub struct PlanEntry {
+    pub image: String,
+    pub tag: String,
+    pub change: PlanChange,
+}
+
+/// What a sync would do with a tag.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PlanChange {
+    Pull,
+    Update,
+    Unconfigured,
+}
+
+/// The kinds of failures the core commands tell apart, for reports and retries.
+#[derive(Debug, thiserror::Error)]
+pub enum PullatorError {
+    /// Docker Hub or a registry could not be reached or gave an unusable answer.
+    #[error(transparent)]
+    Network(anyhow::Error),
+    /// The docker binary could not be run or exited with an error.
+    #[error(transparent)]
+    Docker(anyhow::Error),
+    /// The config or the command line options are unusable.
+    #[error(transparent)]
+    Config(anyhow::Error),
+    /// A repository, image or tag does not exist.
+    #[error(transparent)]
+    NotFound(anyhow::Error),
+}
+
+impl PullatorError {
+    /// The kind as shown in reports, e.g. `not_found`.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Network(_) => "network",
+            Self::Docker(_) => "docker",
+            Self::Config(_) => "config",
+            Self::NotFound(_) => "not_found",
+        }
+    }
+
+    /// Like anyhow's context, but keeps the kind of the error.
+    pub fn context(self, context: impl std::fmt::Display + Send + Sync + 'static) -> Self {
+        match self {
+            Self::Network(error) => Self::Network(error.context(context)),
+            Self::Docker(error) => Self::Docker(error.context(context)),
+            Self::Config(error) => Self::Config(error.context(context)),
+            Self::NotFound(error) => Self::NotFound(error.context(context)),
+        }
+    }
+}
+
+/// How [`push`] pushes the pulled images.
+#[derive(Debug, Clone, Default)]
+pub struct PushOptions {
+    /// The registries to push to, e.g. `registry.corp/mirror`.
+    pub registries: Vec<String>,
+    /// The number of images to push at the same time.
+    pub concurrency: usize,
+    /// Run docker login for every registry before pushing.
+    pub login: bool,
+    /// Only push this platform of multi-arch images, e.g. `linux/arm64`.
+    pub platform: Option<String>,
+    /// Remove the local images once everything is pushed.
+    pub clean: bool,
+    /// Keep the target tags created for the push instead of removing them afterwards.
+    pub keep_local: bool,
+    /// Copy the whole manifest list of every tag between the registries with
+    /// `docker buildx imagetools create`, keeping all of its platforms. Needs the buildx plugin.
+    pub multi_arch: bool,
+    /// Tags to push under another name, keyed by `tag` or the more specific `image:tag`.
+    /// When two tags end up with the same target only the first one is pushed.
+    pub rename: BTreeMap<String, String>,
+}
+
+/// Pushes every active tag of the config, and the upstream tags sharing its digest, to the
+/// registries, adding the outcome of each target to the report.
+pub async fn push(
+    config: &Config,
+    hub: &Hub,
+    docker: &Docker,
+    options: &PushOptions,
+    report: &mut Report,
+) -> Result<(), PullatorError> {
+    let config = &resolve_latest(config, hub).await?;
+
+    if options.login {
+        registry_login(config, docker, &options.registries).await
+    } else {
+        login_configured(
+            config,
+            docker,
+            options.registries.iter().map(String::as_str),
+        )
+        .await
+    }
+    .map_err(PullatorError::Docker)?;
+
+    if options.multi_arch {
+        let found = docker
+            .command()
+            .query()
+            .arg("buildx")
+            .arg("version")
+            .output()
+            .await
+            .context("Failed to run docker buildx")
+            .map_err(PullatorError::Docker)?;
+        if !found.status.success() {
+            return Err(PullatorError::Docker(docker_error(
+                "Running docker buildx for --multi-arch",
+                &found,
+            )));
+        }
+    }
+
+    let keep_local = options.keep_local;
+    let multi_arch = options.multi_arch;
+    push_to_registries(config, hub, options, "Push", report, |job| {
+        let docker = docker.clone();
+        async move {
+            if multi_arch {
+                copy_manifest_list(&docker, &job).await
+            } else {
+                push_image(&docker, &job, keep_local).await
+            }
+        }
+    })
+    .await?;
+
+    if options.clean {
+        clean(config, docker, false, false).await?;
+    }
+
+    Ok(())
+}
+
+/// Where [`mirror`] copies the images to.
+#[derive(Debug, Clone, Default)]
+pub struct MirrorOptions {
+    /// The registries to copy to, e.g. `registry.corp/mirror`.
+    pub registries: Vec<String>,
+    /// The number of images to copy at the same time.
+    pub concurrency: usize,
+    /// Only copy this platform of multi-arch images instead of all of them, e.g. `linux/arm64`.
+    pub platform: Option<String>,
+}
+
+/// Copies the same targets as [`push`] straight from their source registry with skopeo, so
+/// the images never pass through the local docker daemon.
+pub async fn mirror(
+    config: &Config,
+    hub: &Hub,
+    skopeo: &Docker,
+    options: &MirrorOptions,
+    report: &mut Report,
+) -> Result<(), PullatorError> {
+    let config = &resolve_latest(config, hub).await?;
+
+    // skopeo login takes the same arguments as docker login.
+    let sources = config
+        .pull_profiles
+        .values()
+        .filter_map(|profile| profile.registry.as_deref());
+    login_configured(
+        config,
+        skopeo,
+        sources.chain(options.registries.iter().map(String::as_str)),
+    )
+    .await
+    .map_err(PullatorError::Docker)?;
+
+    let targets = PushOptions {
+        registries: options.registries.clone(),
+        concurrency: options.concurrency,
+        platform: options.platform.clone(),
+        ..Default::default()
+    };
+
+    push_to_registries(config, hub, &targets, "Mirror", report, |job| {
+        let skopeo = skopeo.clone();
+        async move { copy_image(&skopeo, &job).await }
+    })
+    .await
+}
+
+async fn copy_image(skopeo: &Docker, job: &PushJob) -> Result<(), PullatorError> {
+    let mut command = skopeo.command();
+    command.arg("copy");
+    match &job.platform {
+        Some(platform) => {
+            let flags = ["--override-os", "--override-arch", "--override-variant"];
+            for (flag, value) in flags.into_iter().zip(platform.split('/')) {
+                command.arg(flag).arg(value);
+            }
+        }
+        None => {
+            command.arg("--all");
+        }
+    }
+
+    let output = command
+        .arg(format!("docker://{}", job.source))
+        .arg(format!("docker://{}", job.target))
+        .output()
+        .await
+        .context("Failed to copy image")
+        .map_err(PullatorError::Docker)?;
+
+    if !output.status.success() {
+        return Err(docker_failure(
+            &format!("Copying {} to {}", job.source, job.target),
+            &output,
+        ));
+    }
+
+    Ok(())
+}
+
+/// Runs a job for every target push_jobs finds in each registry, adding the results to the
+/// report.
+async fn push_to_registries<F, T>(
+    config: &Config,
+    hub: &Hub,
+    options: &PushOptions,
+    name: &str,
+    report: &mut Report,
+    run: F,
+) -> Result<(), PullatorError>
+where
+    F: Fn(PushJob) -> T,
+    T: std::future::Future<Output = Result<(), PullatorError>> + Send + 'static,
+{
+    let verb = name.to_lowercase();
+    let token = hub_token(hub, config.auth.as_ref())
+        .await
+        .map_err(PullatorError::Network)?;
+    let responses = prefetch_tags(config, hub, token.as_deref(), options.concurrency).await;
+    let semaphore = Arc::new(Semaphore::new(options.concurrency.max(1)));
+
+    let mut registries = vec![];
+    for registry in &options.registries {
+        let jobs = match &responses {
+            Ok(responses) => Ok(push_jobs(
+                config,
+                registry,
+                options.platform.as_deref(),
+                &options.rename,
+                responses,
+            )),
+            Err(error) => Err(error),
+        };
+
+        let tasks = match jobs {
+            Ok(jobs) => {
+                let mut tasks = vec![];
+                for job in jobs {
+                    let permit = semaphore
+                        .clone()
+                        .acquire_owned()
+                        .await
+                        .with_context(|| format!("Failed to acquire {verb} permit"))
+                        .map_err(PullatorError::Docker)?;
+
+                    let task = tokio::spawn({
+                        let run = run(job.clone());
+                        async move {
+                            let _permit = permit;
+                            run.await
+                        }
+                    });
+
+                    tasks.push((job, task));
+                }
+
+                Ok(tasks)
+            }
+            Err(error) => Err(error),
+        };
+
+        registries.push((registry, tasks));
+    }
+
+    let mut results = vec![];
+    for (registry, tasks) in registries {
+        let result = match tasks {
+            Ok(tasks) => {
+                let mut failed = false;
+                for (job, task) in tasks {
+                    let result = task
+                        .await
+                        .with_context(|| format!("{name} task panicked"))
+                        .map_err(PullatorError::Docker)?;
+                    report.push(ReportEntry {
+                        image: job.image,
+                        tag: job.tag,
+                        action: ReportAction::Push,
+                        platform: job.platform.clone(),
+                        registry: Some(registry.clone()),
+                        target: Some(job.target.clone()),
+                        status: if result.is_ok() {
+                            ReportStatus::Pushed
+                        } else {
+                            ReportStatus::Failed
+                        },
+                        error: result.as_ref().err().map(|error| format!("{error:#}")),
+                        error_kind: result.as_ref().err().map(PullatorError::kind),
+                    });
+
+                    failed |= result.is_err();
+                }
+
+                Ok(failed)
+            }
+            Err(error) => {
+                for profile in config.pull_profiles.values() {
+                    for tag in profile.active_tags() {
+                        report.push(ReportEntry {
+                            image: profile.image(),
+                            tag: tag.clone(),
+                            action: ReportAction::Push,
+                            platform: options.platform.clone(),
+                            registry: Some(registry.clone()),
+                            target: None,
+                            status: ReportStatus::Failed,
+                            error: Some(format!("{error:#}")),
+                            error_kind: Some(error.kind()),
+                        });
+                    }
+                }
+
+                Err(error)
+            }
+        };
+
+        results.push(result);
+    }
+
+    // The report holds the outcome of every target for the caller's summary.
+    let failed = results
+        .iter()
+        .filter(|result| !matches!(result, Ok(false)))
+        .count();
+    if failed > 0 {
+        return Err(PullatorError::Docker(anyhow::anyhow!(
+            "Failed to {verb} to {failed} registry(s)"
+        )));
+    }
+
+    Ok(())
+}
+
+/// Runs docker login for the host of every registry, with the credentials of the host in the
+/// config registries or else those from the environment or the config auth.
+pub async fn registry_login(
+    config: &Config,
+    docker: &Docker,
+    registries: &[String],
+) -> anyhow::Result<()> {
+    let env = |key: &str| std::env::var(key).ok().filter(|value| !value.is_empty());
+    let auth = config.auth.as_ref();
+
+    let username =
+        env("DOCKER_REGISTRY_USERNAME").or_else(|| auth.and_then(|auth| auth.username.clone()));
+    let password =
+        env("DOCKER_REGISTRY_PASSWORD").or_else(|| auth.and_then(|auth| auth.password.clone()));
+
+    let hosts = registries
+        .iter()
+        .map(|registry| registry_host(registry))
+        .collect::<BTreeSet<_>>();
+
+    // All credentials are looked up first, so a missing one fails before any login.
+    let mut logins = vec![];
+    for host in hosts {
+        let credentials = match (config.credentials(host), &username, &password) {
+            (Some(credentials), _, _) => credentials.resolve(host)?,
+            (None, Some(username), Some(password)) => (username.clone(), password.clone()),
+            (None, _, _) => anyhow::bail!(
+                "--login requires credentials for {host} in the config registries, DOCKER_REGISTRY_USERNAME and DOCKER_REGISTRY_PASSWORD or a username and password in the config auth"
+            ),
+        };
+        logins.push((host, credentials));
+    }
+
+    for (host, (username, password)) in logins {
+        docker_login(docker, host, &username, &password).await?;
+    }
+
+    Ok(())
+}
+
+/// Logs in to the hosts of those registries that have credentials in the config registries.
+pub async fn login_configured<'a>(
+    config: &Config,
+    docker: &Docker,
+    registries: impl IntoIterator<Item = &'a str>,
+) -> anyhow::Result<()> {
+    let hosts = registries
+        .into_iter()
+        .map(registry_host)
+        .filter(|host| config.credentials(host).is_some())
+        .collect::<BTreeSet<_>>();
+
+    for host in hosts {
+        let (username, password) = config
+            .credentials(host)
+            .expect("hosts without credentials are skipped")
+            .resolve(host)?;
+        docker_login(docker, host, &username, &password).await?;
+    }
+
+    Ok(())
+}
+
+async fn docker_login(
+    docker: &Docker,
+    host: &str,
+    username: &str,
+    password: &str,
+) -> anyhow::Result<()> {
+    tracing::info!("Logging in to {host} as {username}");
+
+    let output = docker
+        .command()
+        .arg("login")
+        .arg("--username")
+        .arg(username)
+        .arg("--password-stdin")
+        .arg(host)
+        .input(password.as_bytes())
+        .output()
+        .await
+        .context("Failed to log in")?;
+
+    if !output.status.success() {
+        return Err(docker_error(&format!("Logging in to {host}"), &output));
+    }
+
+    Ok(())
+}
+
+/// The host of a registry like `registry.corp:5000/mirror`.
+fn registry_host(registry: &str) -> &str {
+    registry
+        .trim_end_matches('/')
+        .split('/')
+        .next()
+        .unwrap_or(registry)
+}
+
+#[derive(Debug, Clone)]
+struct PushJob {
+    image: String,
+    tag: String,
+    source: String,
+    target: String,
+    platform: Option<String>,
+}
+
+/// Fetches the tags of every configured image concurrently, so push doesn't wait on them one by one.
+async fn prefetch_tags(
+    config: &Config,
+    hub: &Hub,
+    token: Option<&str>,
+    concurrency: usize,
+) -> Result<HashMap<String, FetchTagsResponse>, PullatorError> {
+    let semaphore = Arc::new(Semaphore::new(concurrency.max(1)));
+    let mut tasks = vec![];
+
+    for profile in config.pull_profiles.values() {
+        let permit = semaphore
+            .clone()
+            .acquire_owned()
+            .await
+            .context("Failed to acquire fetch permit")
+            .map_err(PullatorError::Network)?;
+
+        let task = tokio::spawn({
+            let hub = hub.clone();
+            let profile = profile.clone();
+            let token = token.map(String::from);
+            async move {
+                let _permit = permit;
+                fetch_tags(
+                    &hub,
+                    profile.registry.as_deref(),
+                    profile.library.as_ref(),
+                    &profile.repo,
+                    FetchOptions::default(),
+                    token.as_deref(),
+                )
+                .await
+            }
+        });
+
+        tasks.push((profile.image(), task));
+    }
+
+    let mut responses = HashMap::new();
+    for (image, task) in tasks {
+        let response = task
+            .await
+            .context("Fetch task panicked")
+            .map_err(PullatorError::Network)?
+            .map_err(|error| error.context("Failed to fetch tags"))?;
+        responses.insert(image, response);
+    }
+
+    Ok(responses)
+}
+
+fn push_jobs(
+    config: &Config,
+    registry: &str,
+    platform: Option<&str>,
+    rename: &BTreeMap<String, String>,
+    responses: &HashMap<String, FetchTagsResponse>,
+) -> Vec<PushJob> {
+    // Tags sharing a digest push the same content, so every target is pushed only once.
+    let mut pushed = HashSet::new();
+    let mut jobs = vec![];
+
+    for profile in config.pull_profiles.values() {
+        for tag in profile.active_tags() {
+            let image = profile.image();
+            // Images from other registries are pushed under their path without the host.
+            let path = image_name(profile.library.as_ref(), &profile.repo);
+            let target = |tag: &str| {
+                let tag = rename
+                    .get(&format!("{image}:{tag}"))
+                    .or_else(|| rename.get(tag))
+                    .map_or(tag, String::as_str);
+                format!("{registry}/{path}:{tag}")
+            };
+
+            let response = &responses[&image];
+
+            // Registries only take tags, so a digest is pushed under the tags that point to it.
+            if is_digest(tag) {
+                let targets = response
+                    .results
+                    .iter()
+                    .filter(|item| {
+                        item.digest.as_ref() == Some(tag) && !profile.is_ignored(&item.name)
+                    })
+                    .map(|item| target(&item.name))
+                    .collect::<Vec<_>>();
+                if targets.is_empty() {
+                    tracing::warn!("Skipping {image}@{tag}: no upstream tag points to it");
+                }
+
+                jobs.extend(
+                    targets
+                        .into_iter()
+                        .filter(|target| pushed.insert(target.clone()))
+                        .map(|target| PushJob {
+                            image: image.clone(),
+                            tag: tag.clone(),
+                            source: profile.reference(tag),
+                            target,
+                            platform: platform.map(String::from),
+                        }),
+                );
+                continue;
+            }
+
+            let mut targets = vec![target(tag)];
+
+            let item = response.results.iter().find(|item| &item.name == tag);
+            if let (Some(platform), Some(item)) = (platform, item) {
+                if !item.matches_platform_exactly(platform) {
+                    tracing::warn!("Skipping {image}:{tag}: not published for {platform}");
+                    continue;
+                }
+            }
+
+            // With a platform, aliases must share the digest of that platform's image.
+            if let Some(digest) = item.and_then(|item| item.push_digest(platform)) {
+                targets.extend(
+                    response
+                        .results
+                        .iter()
+                        .filter(|x| {
+                            x.push_digest(platform) == Some(digest) && !profile.is_ignored(&x.name)
+                        })
+                        .map(|item| target(&item.name)),
+                );
+            }
+
+            let source = format!("{}:{}", &image, tag);
+            jobs.extend(
+                targets
+                    .into_iter()
+                    .filter(|target| pushed.insert(target.clone()))
+                    .map(|target| PushJob {
+                        image: image.clone(),
+                        tag: tag.clone(),
+                        source: source.clone(),
+                        target,
+                        platform: platform.map(String::from),
+                    }),
+            );
+        }
+    }
+
+    jobs
+}
+
+/// Copies every platform of the source to the target registry without touching local images.
+async fn copy_manifest_list(docker: &Docker, job: &PushJob) -> Result<(), PullatorError> {
+    let output = docker
+        .command()
+        .arg("buildx")
+        .arg("imagetools")
+        .arg("create")
+        .arg("--tag")
+        .arg(&job.target)
+        .arg(&job.source)
+        .output()
+        .await
+        .context("Failed to copy manifest list")
+        .map_err(PullatorError::Docker)?;
+
+    if !output.status.success() {
+        return Err(docker_failure(
+            &format!(
+                "Copying the manifest list of {} to {}",
+                job.source, job.target
+            ),
+            &output,
+        ));
+    }
+
+    Ok(())
+}
+
+async fn push_image(docker: &Docker, job: &PushJob, keep_local: bool) -> Result<(), PullatorError> {
+    let PushJob {
+        source,
+        target,
+        platform,
+        ..
+    } = job;
+
+    // Pushing an image back under its own name must not remove the source afterwards.
+    let in_place = same_reference(source, target);
+    if !in_place {
+        let output = docker
+            .command()
+            .arg("tag")
+            .arg(source)
+            .arg(target)
+            .output()
+            .await
+            .context("Failed to tag image")
+            .map_err(PullatorError::Docker)?;
+
+        if !output.status.success() {
+            return Err(docker_failure(
+                &format!("Tagging {source} as {target}"),
+                &output,
+            ));
+        }
+    }
+
+    let mut command = docker.command();
+    command.arg("push");
+    if let Some(platform) = platform {
+        command.arg("--platform").arg(platform);
+    }
+
+    let output = command
+        .arg(target)
+        .output()
+        .await
+        .context("Failed to push image")
+        .map_err(PullatorError::Docker)?;
+
+    if !output.status.success() {
+        return Err(docker_failure(&format!("Pushing {target}"), &output));
+    }
+
+    if keep_local || in_place {
+        return Ok(());
+    }
+
+    let output = docker
+        .command()
+        .arg("image")
+        .arg("rm")
+        .arg(target)
+        .output()
+        .await
+        .context("Failed to remove image")
+        .map_err(PullatorError::Docker)?;
+
+    if !output.status.success() && !is_missing_image(&output) {
+        return Err(docker_failure(&format!("Removing {target}"), &output));
+    }
+
+    Ok(())
+}
+
+/// Updates the pinned digests of the config to the current ones upstream.
+pub async fn resolve_digests(config: &mut Config, hub: &Hub) -> anyhow::Result<()> {
+    let token = hub_token(hub, config.auth.as_ref()).await?;
+
+    for (image, profile) in config.pull_profiles.iter_mut() {
+        if profile.digests.is_empty() {
+            continue;
+        }
+
+        let response = fetch_tags(
+            hub,
+            profile.registry.as_deref(),
+            profile.library.as_ref(),
+            &profile.repo,
+            FetchOptions::default(),
+            token.as_deref(),
+        )
+        .await?;
+
+        for (tag, digest) in profile.digests.iter_mut() {
+            let item = response.results.iter().find(|item| &item.name == tag);
+            let Some(latest) = item.and_then(|item| item.digest.as_ref()) else {
+                tracing::warn!("No digest found for {image}:{tag}, keeping {digest}");
+                continue;
+            };
+
+            if latest != digest {
+                tracing::info!("Resolved {image}:{tag} to {latest}");
+                digest.clone_from(latest);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// A `@latest:N` or `@latest:N:suffix` entry in the tags of a profile, expanded to the newest
+/// N version tags whenever the profile is pulled or pushed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LatestDirective {
+    pub count: usize,
+    pub suffix: Option<String>,
+}
+
+impl LatestDirective {
+    /// Parses a `@latest:N[:suffix]` tag.
+    pub fn parse(tag: &str) -> Option<Self> {
+        let rest = tag.strip_prefix("@latest:")?;
+        let (count, suffix) = match rest.split_once(':') {
+            Some((count, suffix)) if !suffix.is_empty() => (count, Some(suffix.to_string())),
+            Some(_) => return None,
+            None => (rest, None),
+        };
+
+        Some(Self {
+            count: count.parse().ok().filter(|count| *count > 0)?,
+            suffix,
+        })
+    }
+
+    /// Selects the tags of the directive among the upstream tags.
+    pub fn resolve(&self, tags: &[String]) -> Vec<String> {
+        latest_tags(tags, self.count, self.suffix.as_deref())
+    }
+}
+
+impl std::fmt::Display for LatestDirective {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.suffix {
+            Some(suffix) => write!(f, "@latest:{}:{suffix}", self.count),
+            None => write!(f, "@latest:{}", self.count),
+        }
+    }
+}
+
+/// Replaces the @latest directives in the tags with the current upstream tags they select,
+/// and adds the upstream tags matching the pattern of each profile.
+pub async fn resolve_latest(config: &Config, hub: &Hub) -> Result<Config, PullatorError> {
+    resolve_tags(config, hub, true).await
+}
+
+/// Adds the upstream tags matching the pattern of each profile, leaving @latest directives.
+///
+/// Like every tag list, the upstream tags come from the cache of the hub when it has one, so
+/// commands run within its ttl don't fetch them again.
+pub async fn resolve_patterns(config: &Config, hub: &Hub) -> Result<Config, PullatorError> {
+    resolve_tags(config, hub, false).await
+}
+
+async fn resolve_tags(config: &Config, hub: &Hub, latest: bool) -> Result<Config, PullatorError> {
+    let mut config = config.clone();
+    let dynamic = |profile: &PullProfile| {
+        profile.pattern.is_some()
+            || (latest
+                && profile
+                    .tags
+                    .iter()
+                    .any(|tag| LatestDirective::parse(tag).is_some()))
+    };
+    if !config.pull_profiles.values().any(dynamic) {
+        return Ok(config);
+    }
+
+    let token = hub_token(hub, config.auth.as_ref())
+        .await
+        .map_err(PullatorError::Network)?;
+
+    for profile in config.pull_profiles.values_mut() {
+        if !dynamic(profile) {
+            continue;
+        }
+
+        let response = fetch_tags(
+            hub,
+            profile.registry.as_deref(),
+            profile.library.as_ref(),
+            &profile.repo,
+            FetchOptions::default(),
+            token.as_deref(),
+        )
+        .await?;
+        let upstream = response
+            .results
+            .into_iter()
+            .map(|item| item.name)
+            .collect::<Vec<_>>();
+
+        profile.tags = profile.resolve_tags(&upstream, latest)?;
+    }
+
+    Ok(config)
+}
+
+/// The newest `count` version tags with the given suffix, newest first.
+pub fn latest_tags(tags: &[String], count: usize, suffix: Option<&str>) -> Vec<String> {
+    let mut versions = tags
+        .iter()
+        .filter_map(|tag| TagVersion::parse(tag).map(|version| (version, tag)))
+        .filter(|(version, _)| version.suffix.as_deref() == suffix)
+        .collect::<Vec<_>>();
+
+    versions.sort_by(|(a, _), (b, _)| b.cmp(a));
+
+    versions
+        .into_iter()
+        .take(count)
+        .map(|(_, tag)| tag.clone())
+        .collect()
+}
+
+/// A tag like `v1.2.3-alpine`, ordered by its numeric components.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TagVersion {
+    numbers: Vec<u64>,
+    suffix: Option<String>,
+}
+
+impl TagVersion {
+    /// Parses a tag like `v1.2.3-alpine`, or `None` if it is not a version.
+    pub fn parse(tag: &str) -> Option<Self> {
+        let tag = tag.strip_prefix(['v', 'V']).unwrap_or(tag);
+        let (version, suffix) = match tag.split_once('-') {
+            Some((version, suffix)) => (version, Some(suffix.to_string())),
+            None => (tag, None),
+        };
+
+        let numbers = version
+            .split('.')
+            .map(|part| part.parse().ok())
+            .collect::<Option<Vec<u64>>>()?;
+
+        Some(Self { numbers, suffix })
+    }
+}
+
+/// Removes the local images of the active tags, by their pinned digest with `by_digest`.
+pub async fn clean(
+    config: &Config,
+    docker: &Docker,
+    force: bool,
+    by_digest: bool,
+) -> Result<(), PullatorError> {
+    let mut failed = vec![];
+
+    for profile in config.pull_profiles.values() {
+        let image = profile.image();
+
+        for tag in profile.active_tags() {
+            let reference = match profile.digests.get(tag) {
+                Some(digest) if by_digest => format!("{image}@{digest}"),
+                _ => profile.reference(tag),
+            };
+
+            let mut command = docker.command();
+            command.arg("image").arg("rm");
+            if force {
+                command.arg("--force");
+            }
+
+            let result = command
+                .arg(&reference)
+                .output()
+                .await
+                .context("Failed to remove image")
+                .map_err(PullatorError::Docker)
+                .and_then(|output| {
+                    if output.status.success() || is_missing_image(&output) {
+                        Ok(())
+                    } else {
+                        Err(docker_failure(&format!("Removing {reference}"), &output))
+                    }
+                });
+
+            if let Err(error) = result {
+                failed.push((reference, error));
+            }
+        }
+    }
+
+    if !failed.is_empty() {
+        for (reference, error) in &failed {
+            tracing::error!("Failed to remove {reference}: {error:#}");
+        }
+
+        return Err(PullatorError::Docker(anyhow::anyhow!(
+            "Failed to remove {} image(s)",
+            failed.len()
+        )));
+    }
+
+    Ok(())
+}
+
+/// The tags of an image present locally.
+pub async fn local_tags(docker: &Docker, image: &str) -> anyhow::Result<Vec<String>> {
+    let output = docker
+        .command()
+        .query()
+        .arg("image")
+        .arg("ls")
+        .arg(image)
+        .arg("--format")
+        .arg("{{.Tag}}")
+        .output()
+        .await
+        .context("Failed to list images")?;
+
+    if !output.status.success() {
+        return Err(docker_error(&format!("Listing {image}"), &output));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(str::trim)
+        .filter(|tag| !tag.is_empty() && *tag != "<none>")
+        .map(str::to_string)
+        .collect())
+}
+
+/// Runs a docker compatible binary, e.g. docker or podman.
+#[derive(Debug, Clone)]
+pub struct Docker {
+    bin: String,
+    output: DockerOutput,
+    dry_run: bool,
+    timeout: Option<Duration>,
+    progress: Option<MultiProgress>,
+}
+
+impl Docker {
+    /// Runs `bin`, e.g. `docker`.
+    pub fn new(bin: String, output: DockerOutput) -> Self {
+        Self {
+            bin,
+            output,
+            dry_run: false,
+            timeout: None,
+            progress: None,
+        }
+    }
+
+    /// Kills commands that run longer than the timeout.
+    pub fn with_timeout(self, timeout: Option<Duration>) -> Self {
+        Self { timeout, ..self }
+    }
+
+    /// Draws layer progress bars with [`Progress::Tui`] when stderr is a terminal.
+    pub fn with_progress(self, progress: Progress) -> Self {
+        let progress =
+            (progress == Progress::Tui && std::io::stderr().is_terminal()).then(MultiProgress::new);
+        Self { progress, ..self }
+    }
+
+    /// Adds a layer progress bar for a pull when --progress tui is in effect.
+    fn progress_bar(&self, label: &str) -> Option<ProgressBar> {
+        let bar = self.progress.as_ref()?.add(ProgressBar::new(0));
+        bar.set_style(
+            ProgressStyle::with_template("{prefix} [{bar:30}] {pos}/{len} layers {msg}")
+                .expect("progress template should be valid")
+                .progress_chars("=> "),
+        );
+        bar.set_prefix(label.to_string());
+        Some(bar)
+    }
+
+    /// Removes the finished progress bars, the pull summary repeats their outcome.
+    fn clear_progress(&self) {
+        if let Some(progress) = &self.progress {
+            let _ = progress.clear();
+        }
+    }
+
+    /// Runs another binary with the same settings, e.g. skopeo for [`mirror`].
+    pub fn with_bin(&self, bin: String) -> Self {
+        Self {
+            bin,
+            ..self.clone()
+        }
+    }
+
+    /// Logs the commands that change anything instead of running them.
+    pub fn with_dry_run(&self, dry_run: bool) -> Self {
+        Self {
+            dry_run,
+            ..self.clone()
+        }
+    }
+
+    /// Whether commands are logged instead of run.
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    /// Checks that the binary can be run at all, before any command depends on it.
+    pub async fn check_installed(&self) -> anyhow::Result<()> {
+        match self.command().query().arg("--version").output().await {
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => Err(anyhow::anyhow!(
+                "{} binary not found; install Docker or set --docker-bin",
+                self.bin
+            )),
+            result => result
+                .map(|_| ())
+                .with_context(|| format!("Failed to run {}", self.bin)),
+        }
+    }
+
+    /// Starts a command with the settings of this docker.
+    pub fn command(&self) -> DockerCommand {
+        DockerCommand {
+            command: {
+                let mut command = Command::new(&self.bin);
+                // Commands still running when the runtime shuts down, e.g. after Ctrl-C, are killed.
+                command.kill_on_drop(true);
+                command
+            },
+            bin: self.bin.clone(),
+            args: vec![],
+            envs: vec![],
+            input: None,
+            output: self.output,
+            dry_run: self.dry_run,
+            timeout: self.timeout,
+            progress: None,
+        }
+    }
+}
+
+/// The tracing target of the commands dry runs skip, logged at info level.
+pub const DRY_RUN_TARGET: &str = "docker_pullator::dry_run";
+
+/// A docker command being built, run with [`DockerCommand::output`].
+pub struct DockerCommand {
+    command: Command,
+    bin: String,
+    args: Vec<String>,
+    envs: Vec<String>,
+    input: Option<Vec<u8>>,
+    output: DockerOutput,
+    dry_run: bool,
+    timeout: Option<Duration>,
+    progress: Option<ProgressBar>,
+}
+
+impl DockerCommand {
+    /// Adds an argument, shown as is in dry runs.
+    pub fn arg(&mut self, arg: impl AsRef<str>) -> &mut Self {
+        self.args.push(arg.as_ref().to_string());
+        self.command.arg(arg.as_ref());
+        self
+    }
+
+    /// Sets an environment variable for the command, shown in front of it in dry runs.
+    pub fn env(&mut self, key: &str, value: &str) -> &mut Self {
+        self.envs.push(format!("{key}={value}"));
+        self.command.env(key, value);
+        self
+    }
+
+    /// Writes the input to stdin instead of passing it as an argument, e.g. for secrets.
+    pub fn input(&mut self, input: impl Into<Vec<u8>>) -> &mut Self {
+        self.input = Some(input.into());
+        self
+    }
+
+    /// Shows the layer lines of a pull on the bar instead of printing the output.
+    fn progress(&mut self, bar: Option<ProgressBar>) -> &mut Self {
+        self.progress = bar;
+        self
+    }
+
+    /// Captures the output of a read-only command, which also runs during dry runs.
+    pub fn query(&mut self) -> &mut Self {
+        self.output = DockerOutput::Capture;
+        self.dry_run = false;
+        self
+    }
+
+    fn skip(&self) -> bool {
+        if self.dry_run {
+            let envs = self
+                .envs
+                .iter()
+                .map(|env| format!("{env} "))
+                .collect::<String>();
+            tracing::info!(target: DRY_RUN_TARGET, "dry run: {envs}{} {}", self.bin, self.args.join(" "));
+        }
+
+        self.dry_run
+    }
+
+    /// Runs the command like [`DockerCommand::output`], only returning its exit status.
+    pub async fn status(&mut self) -> std::io::Result<ExitStatus> {
+        self.output().await.map(|output| output.status)
+    }
+
+    /// Runs the command, failing with [`std::io::ErrorKind::TimedOut`] after the timeout.
+    pub async fn output(&mut self) -> std::io::Result<Output> {
+        if self.skip() {
+            return Ok(Output {
+                status: ExitStatus::default(),
+                stdout: vec![],
+                stderr: vec![],
+            });
+        }
+
+        let Some(timeout) = self.timeout else {
+            return self.run().await;
+        };
+
+        match tokio::time::timeout(timeout, self.run()).await {
+            Ok(output) => output,
+            // Dropping the unfinished run kills docker and discards its partial output.
+            Err(_) => Err(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                format!(
+                    "{} {} timed out after {}",
+                    self.bin,
+                    self.args.join(" "),
+                    humantime::format_duration(timeout)
+                ),
+            )),
+        }
+    }
+
+    async fn run(&mut self) -> std::io::Result<Output> {
+        if let Some(bar) = self.progress.clone() {
+            return self.run_with_progress(&bar).await;
+        }
+
+        match self.output {
+            DockerOutput::Inherit => {
+                self.command.stdout(Stdio::inherit());
+                self.command.stderr(Stdio::inherit());
+                self.spawn().await?.wait_with_output().await
+            }
+            DockerOutput::Capture => {
+                self.command.stdout(Stdio::piped());
+                self.command.stderr(Stdio::piped());
+                self.spawn().await?.wait_with_output().await
+            }
+            DockerOutput::Tee => {
+                self.command.stdout(Stdio::inherit());
+                self.command.stderr(Stdio::piped());
+
+                let mut child = self.spawn().await?;
+                let stderr = child.stderr.take().expect("stderr should be piped");
+
+                // The error output is passed through as docker writes it, like the inherited
+                // stdout, and kept for the failure message.
+                let mut captured = vec![];
+                let mut terminal = tokio::io::stderr();
+                let mut lines = BufReader::new(stderr).lines();
+                while let Some(line) = lines.next_line().await? {
+                    let line = format!("{line}\n");
+                    terminal.write_all(line.as_bytes()).await?;
+                    captured.extend_from_slice(line.as_bytes());
+                }
+
+                Ok(Output {
+                    status: child.wait().await?,
+                    stdout: vec![],
+                    stderr: captured,
+                })
+            }
+        }
+    }
+
+    async fn run_with_progress(&mut self, bar: &ProgressBar) -> std::io::Result<Output> {
+        self.command.stdout(Stdio::piped());
+        self.command.stderr(Stdio::piped());
+
+        let mut child = self.spawn().await?;
+        let stdout = child.stdout.take().expect("stdout should be piped");
+        let mut stderr = child.stderr.take().expect("stderr should be piped");
+
+        // Both pipes are drained at once, so a full stderr pipe can't stall docker.
+        let mut captured = vec![];
+        let (progress, errors) = tokio::join!(
+            async {
+                let mut layers = HashMap::new();
+                let mut lines = BufReader::new(stdout).lines();
+                while let Some(line) = lines.next_line().await? {
+                    track_layer(bar, &mut layers, &line);
+                }
+
+                std::io::Result::Ok(())
+            },
+            stderr.read_to_end(&mut captured)
+        );
+        progress?;
+        errors?;
+
+        Ok(Output {
+            status: child.wait().await?,
+            stdout: vec![],
+            stderr: captured,
+        })
+    }
+
+    async fn spawn(&mut self) -> std::io::Result<Child> {
+        let not_found = |error: std::io::Error| {
+            if error.kind() != std::io::ErrorKind::NotFound {
+                return error;
+            }
+            std::io::Error::new(error.kind(), format!("{} binary not found", self.bin))
+        };
+
+        let Some(input) = self.input.take() else {
+            self.command.stdin(Stdio::null());
+            return self.command.spawn().map_err(not_found);
+        };
+
+        self.command.stdin(Stdio::piped());
+        let mut child = self.command.spawn().map_err(not_found)?;
+
+        // Dropping stdin after the write closes it, so docker stops reading.
+        let mut stdin = child.stdin.take().expect("stdin should be piped");
+        stdin.write_all(&input).await?;
+        drop(stdin);
+
+        Ok(child)
+    }
+}
+
+/// Follows the layer lines of docker pull, e.g. `a2abf6c4d29d: Pull complete`.
+fn track_layer(bar: &ProgressBar, layers: &mut HashMap<String, bool>, line: &str) {
+    let Some((id, status)) = line.trim().split_once(": ") else {
+        return;
+    };
+    if id.len() != 12 || !id.chars().all(|char| char.is_ascii_hexdigit()) {
+        return;
+    }
+
+    let done = matches!(status, "Pull complete" | "Already exists");
+    *layers.entry(id.to_string()).or_default() |= done;
+
+    bar.set_length(layers.len() as u64);
+    bar.set_position(layers.values().filter(|done| **done).count() as u64);
+    bar.set_message(status.to_string());
+}
+
+/// Builds an error for a failed docker command from the last lines of its error output.
+pub fn docker_error(action: &str, output: &Output) -> anyhow::Error {
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let lines = stderr
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>();
+    let tail = &lines[lines.len().saturating_sub(5)..];
+
+    if tail.is_empty() {
+        anyhow::anyhow!("{action} failed with status: {}", output.status)
+    } else {
+        anyhow::anyhow!(
+            "{action} failed with status: {}\n    {}",
+            output.status,
+            tail.join("\n    ")
+        )
+    }
+}
+
+/// Whether docker image rm failed only because the image is already gone.
+pub fn is_missing_image(output: &Output) -> bool {
+    String::from_utf8_lossy(&output.stderr)
+        .to_lowercase()
+        .contains("no such image")
+}
+
+/// Whether docker pull failed because content trust found no signature for the tag.
+fn is_unsigned(output: &Output) -> bool {
+    let stderr = String::from_utf8_lossy(&output.stderr).to_lowercase();
+    ["trust data", "no valid signatures"]
+        .iter()
+        .any(|message| stderr.contains(message))
+}
+
+/// Like docker_error, but tells missing images apart from other docker failures.
+fn docker_failure(action: &str, output: &Output) -> PullatorError {
+    let stderr = String::from_utf8_lossy(&output.stderr).to_lowercase();
+    let error = docker_error(action, output);
+
+    if ["no such image", "not found", "manifest unknown"]
+        .iter()
+        .any(|message| stderr.contains(message))
+    {
+        PullatorError::NotFound(error)
+    } else {
+        PullatorError::Docker(error)
+    }
+}
+
+/// Pulls every active tag of the config after resolving its @latest directives, adding the
+/// outcome of each to the report.
+pub async fn pull(
+    config: &Config,
+    hub: &Hub,
+    docker: &Docker,
+    options: &PullOptions,
+    report: &mut Report,
+) -> Result<(), PullatorError> {
+    let config = &resolve_latest(config, hub).await?;
+    let sources = config
+        .pull_profiles
+        .values()
+        .filter_map(|profile| profile.registry.as_deref().or(options.mirror.as_deref()));
+    login_configured(config, docker, sources)
+        .await
+        .map_err(PullatorError::Docker)?;
+
+    let semaphore = Arc::new(Semaphore::new(options.concurrency.max(1)));
+    let mut tasks = vec![];
+
+    let token = if options.check_digest {
+        hub_token(hub, config.auth.as_ref())
+            .await
+            .map_err(PullatorError::Network)?
+    } else {
+        None
+    };
+
+    for profile in config.pull_profiles.values() {
+        let response = if options.check_digest {
+            let response = fetch_tags(
+                hub,
+                profile.registry.as_deref(),
+                profile.library.as_ref(),
+                &profile.repo,
+                FetchOptions::default(),
+                token.as_deref(),
+            )
+            .await?;
+            Some(response)
+        } else {
+            None
+        };
+
+        let platforms = if profile.platforms.is_empty() {
+            vec![options.platform.clone()]
+        } else {
+            profile.platforms.iter().cloned().map(Some).collect()
+        };
+
+        for tag in profile.active_tags() {
+            let mut jobs = vec![];
+            for platform in &platforms {
+                let pinned = profile.pinned_digest(tag);
+
+                // Mirrors stand in for Docker Hub, other registries are pulled directly.
+                let source_image = match &options.mirror {
+                    Some(mirror) if profile.registry.is_none() => profile.mirror_image(mirror),
+                    _ => profile.image(),
+                };
+                let source = match pinned {
+                    Some(digest) => format!("{source_image}@{digest}"),
+                    None => format!("{source_image}:{tag}"),
+                };
+                // Digest references can't be retagged, so they keep the name they were pulled by.
+                let reference = if is_digest(tag) {
+                    source.clone()
+                } else {
+                    profile.reference(tag)
+                };
+
+                let digest = pinned.cloned().or_else(|| {
+                    response
+                        .as_ref()
+                        .and_then(|response| response.results.iter().find(|item| &item.name == tag))
+                        .and_then(|item| item.digest.clone())
+                });
+
+                jobs.push(PullJob {
+                    image: profile.image(),
+                    tag: tag.clone(),
+                    source,
+                    reference,
+                    platform: platform.clone(),
+                    digest,
+                });
+            }
+
+            let permit = semaphore
+                .clone()
+                .acquire_owned()
+                .await
+                .context("Failed to acquire pull permit")
+                .map_err(PullatorError::Docker)?;
+
+            // The platforms of a tag pull to the same local tag, so they take turns.
+            let task = tokio::spawn({
+                let jobs = jobs.clone();
+                let docker = docker.clone();
+                let options = options.clone();
+                async move {
+                    let _permit = permit;
+                    let mut results = vec![];
+                    for job in &jobs {
+                        results.push(pull_image(&docker, job, &options).await);
+                    }
+                    results
+                }
+            });
+
+            tasks.push((jobs, task));
+        }
+    }
+
+    let mut results = vec![];
+    for (jobs, task) in tasks {
+        let task_results = task
+            .await
+            .context("Pull task panicked")
+            .map_err(PullatorError::Docker)?;
+        results.extend(jobs.into_iter().zip(task_results));
+    }
+
+    let mut failed = 0;
+    for (job, result) in results {
+        report.push(ReportEntry {
+            image: job.image.clone(),
+            tag: job.tag.clone(),
+            action: ReportAction::Pull,
+            platform: job.platform.clone(),
+            registry: None,
+            target: None,
+            status: match result {
+                Ok(PullOutcome::Pulled) => ReportStatus::Pulled,
+                Ok(PullOutcome::Skipped) => ReportStatus::Skipped,
+                Err(_) => ReportStatus::Failed,
+            },
+            error: result.as_ref().err().map(|error| format!("{error:#}")),
+            error_kind: result.as_ref().err().map(PullatorError::kind),
+        });
+
+        failed += usize::from(result.is_err());
+    }
+
+    // The report holds the outcome of every tag for the caller's summary.
+    docker.clear_progress();
+
+    if failed > 0 {
+        return Err(PullatorError::Docker(anyhow::anyhow!(
+            "Failed to pull {failed} image(s)"
+        )));
+    }
+
+    Ok(())
+}
+
+/// The results of the pulls and pushes of a run, for the JSON report and metrics.
+#[derive(Debug, Default)]
+pub struct Report {
+    entries: Vec<ReportEntry>,
+}
+
+impl Report {
+    /// The results recorded so far, in the order the pulls and pushes were started.
+    pub fn entries(&self) -> &[ReportEntry] {
+        &self.entries
+    }
+
+    fn push(&mut self, entry: ReportEntry) {
+        self.entries.push(entry);
+    }
+
+    /// The config narrowed to the tags pulled or skipped on every platform, with the
+    /// @latest directives and patterns replaced by the tags they were resolved to.
+    pub fn pulled_config(&self, config: &Config) -> Config {
+        let pulls = self
+            .entries
+            .iter()
+            .filter(|entry| entry.action == ReportAction::Pull)
+            .collect::<Vec<_>>();
+
+        let mut config = config.clone();
+        for profile in config.pull_profiles.values_mut() {
+            let image = profile.image();
+            let mut tags = vec![];
+            for entry in pulls.iter().filter(|entry| entry.image == image) {
+                let failed = pulls.iter().any(|other| {
+                    other.image == image
+                        && other.tag == entry.tag
+                        && other.status == ReportStatus::Failed
+                });
+                if !failed && !tags.contains(&entry.tag) {
+                    tags.push(entry.tag.clone());
+                }
+            }
+
+            profile.tags = tags;
+            profile.pattern = None;
+        }
+        config
+            .pull_profiles
+            .retain(|_, profile| !profile.tags.is_empty());
+
+        config
+    }
+
+    /// The entries as the JSON report.
+    pub fn to_json(&self) -> anyhow::Result<String> {
+        serde_json::to_string_pretty(&self.entries).context("Failed to serialize report")
+    }
+
+    /// Writes the entries as Prometheus metrics, along with whether the run succeeded.
+    pub fn write_metrics(&self, path: &str, success: bool) -> anyhow::Result<()> {
+        write_atomically(path, self.metrics(success).as_bytes())
+            .context("Failed to write metrics file")
+    }
+
+    fn metrics(&self, success: bool) -> String {
+        use std::fmt::Write;
+
+        const METRICS: [(&str, ReportAction, ReportStatus, &str); 5] = [
+            (
+                "pullator_pull_success",
+                ReportAction::Pull,
+                ReportStatus::Pulled,
+                "Tags pulled",
+            ),
+            (
+                "pullator_pull_skipped",
+                ReportAction::Pull,
+                ReportStatus::Skipped,
+                "Tags skipped instead of pulled",
+            ),
+            (
+                "pullator_pull_failure",
+                ReportAction::Pull,
+                ReportStatus::Failed,
+                "Tags that failed to pull",
+            ),
+            (
+                "pullator_push_success",
+                ReportAction::Push,
+                ReportStatus::Pushed,
+                "Targets pushed",
+            ),
+            (
+                "pullator_push_failure",
+                ReportAction::Push,
+                ReportStatus::Failed,
+                "Targets that failed to push",
+            ),
+        ];
+
+        let mut metrics = String::new();
+        for (name, action, status, help) in METRICS {
+            let _ = writeln!(metrics, "# HELP {name} {help} in the last run.");
+            // Every run rewrites the file, so the counts are gauges of the last run.
+            let _ = writeln!(metrics, "# TYPE {name} gauge");
+
+            let images = self
+                .entries
+                .iter()
+                .filter(|entry| entry.action == action)
+                .map(|entry| entry.image.as_str())
+                .collect::<BTreeSet<_>>();
+
+            for image in images {
+                let count = self
+                    .entries
+                    .iter()
+                    .filter(|entry| {
+                        entry.image == image && entry.action == action && entry.status == status
+                    })
+                    .count();
+                let image = image.replace('\\', "\\\\").replace('"', "\\\"");
+                let _ = writeln!(metrics, "{name}{{image=\"{image}\"}} {count}");
+            }
+        }
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let _ = writeln!(
+            metrics,
+            "# HELP pullator_last_run_timestamp Unix time of the last run.\n\
+             # TYPE pullator_last_run_timestamp gauge\n\
+             pullator_last_run_timestamp {timestamp}\n\
+             # HELP pullator_last_run_success Whether the last run succeeded.\n\
+             # TYPE pullator_last_run_success gauge\n\
+             pullator_last_run_success {}",
+            u8::from(success)
+        );
+
+        metrics
+    }
+}
+
+/// The result of pulling a tag or pushing it to a target.
+#[derive(Debug, Serialize)]
+pub struct ReportEntry {
+    pub image: String,
+    pub tag: String,
+    pub action: ReportAction,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub platform: Option<String>,
+    /// The registry of a push, also set when the whole registry failed before any target.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub registry: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    pub status: ReportStatus,
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_kind: Option<&'static str>,
+}
+
+/// Whether a report entry is a pull or a push.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReportAction {
+    Pull,
+    Push,
+}
+
+/// How a pull or push ended.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReportStatus {
+    Pulled,
+    Skipped,
+    Pushed,
+    Failed,
+}
+
+#[derive(Debug, Clone)]
+struct PullJob {
+    image: String,
+    tag: String,
+    source: String,
+    reference: String,
+    platform: Option<String>,
+    digest: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum PullOutcome {
+    Pulled,
+    Skipped,
+}
+
+impl PullJob {
+    fn label(&self) -> String {
+        match &self.platform {
+            Some(platform) => format!("{} ({platform})", self.reference),
+            None => self.reference.clone(),
+        }
+    }
+}
+
+async fn pull_image(
+    docker: &Docker,
+    job: &PullJob,
+    options: &PullOptions,
+) -> Result<PullOutcome, PullatorError> {
+    if let Some(reason) = skip_reason(docker, job, options)
+        .await
+        .map_err(PullatorError::Docker)?
+    {
+        tracing::info!("Skipping {}: {reason}", job.label());
+        return Ok(PullOutcome::Skipped);
+    }
+
+    let PullOptions {
+        retries,
+        retry_delay,
+        ..
+    } = *options;
+    let reference = &job.source;
+    let mut attempt = 0;
+    let bar = docker.progress_bar(&job.label());
+
+    let result: Result<PullOutcome, PullatorError> = async {
+        loop {
+            let mut command = docker.command();
+            command.arg("pull");
+            if let Some(platform) = &job.platform {
+                command.arg("--platform").arg(platform);
+            }
+            command.progress(bar.clone());
+            if options.verify_signatures {
+                command.env("DOCKER_CONTENT_TRUST", "1");
+            }
+
+            let failure = match command.arg(reference).output().await {
+                Ok(output) if output.status.success() => {
+                    if job.source != job.reference {
+                        retag_source(docker, job)
+                            .await
+                            .map_err(PullatorError::Docker)?;
+                    }
+
+                    return Ok(PullOutcome::Pulled);
+                }
+                // Missing signatures won't show up on a retry.
+                Ok(output) if attempt >= retries || is_unsigned(&output) => {
+                    return Err(docker_failure("Pull", &output))
+                }
+                Ok(output) => format!("failed with status: {}", output.status),
+                // Timed out pulls are retried like failed ones.
+                Err(error) if error.kind() == std::io::ErrorKind::TimedOut && attempt < retries => {
+                    format!("failed: {error}")
+                }
+                Err(error) => {
+                    return Err(PullatorError::Docker(
+                        anyhow::Error::new(error).context("Failed to pull image"),
+                    ))
+                }
+            };
+
+            let delay = retry_delay.saturating_mul(2u32.saturating_pow(attempt));
+            attempt += 1;
+
+            tracing::warn!(
+                "Pull of {reference} {failure}, retrying in {} (attempt {attempt} of {retries})",
+                humantime::format_duration(delay)
+            );
+
+            if let Some(bar) = &bar {
+                bar.set_message(format!("retrying, attempt {attempt} of {retries}"));
+            }
+
+            tokio::time::sleep(delay).await;
+        }
+    }
+    .await;
+
+    if let Some(bar) = &bar {
+        bar.finish_with_message(if result.is_ok() { "pulled" } else { "failed" });
+    }
+
+    result
+}
+
+async fn skip_reason(
+    docker: &Docker,
+    job: &PullJob,
+    options: &PullOptions,
+) -> anyhow::Result<Option<&'static str>> {
+    if options.force || (!options.if_missing && !options.check_digest) {
+        return Ok(None);
+    }
+
+    let Some(local) = local_digests(docker, &job.reference).await? else {
+        return Ok(None);
+    };
+
+    if !options.check_digest {
+        return Ok(Some("already present"));
+    }
+
+    let up_to_date = job
+        .digest
+        .as_ref()
+        .is_some_and(|digest| has_digest(&local, digest));
+
+    Ok(up_to_date.then_some("local digest matches Docker Hub"))
+}
+
+/// Whether a configured tag is an immutable digest like `sha256:<64 hex digits>`.
+pub fn is_digest(tag: &str) -> bool {
+    tag.strip_prefix("sha256:")
+        .is_some_and(|hex| hex.len() == 64 && hex.chars().all(|char| char.is_ascii_hexdigit()))
+}
+
+/// Whether one of the local repo digests, e.g. `redis@sha256:...`, is the digest.
+pub fn has_digest(local: &[String], digest: &str) -> bool {
+    local
+        .iter()
+        .any(|local| local.rsplit('@').next() == Some(digest))
+}
+
+/// The repo digests of a local image, or `None` if it is not present.
+pub async fn local_digests(
+    docker: &Docker,
+    reference: &str,
+) -> anyhow::Result<Option<Vec<String>>> {
+    let output = docker
+        .command()
+        .query()
+        .arg("image")
+        .arg("inspect")
+        .arg("--format")
+        .arg("{{json .RepoDigests}}")
+        .arg(reference)
+        .output()
+        .await
+        .context("Failed to inspect image")?;
+
+    if !output.status.success() {
+        return Ok(None);
+    }
+
+    serde_json::from_slice(&output.stdout)
+        .context("Failed to parse image digests")
+        .map(Some)
+}
+
+/// The uncompressed size in bytes of a local image, or `None` if it isn't present.
+pub async fn local_size(docker: &Docker, reference: &str) -> anyhow::Result<Option<u64>> {
+    let output = docker
+        .command()
+        .query()
+        .arg("image")
+        .arg("inspect")
+        .arg("--format")
+        .arg("{{.Size}}")
+        .arg(reference)
+        .output()
+        .await
+        .context("Failed to inspect image")?;
+
+    if !output.status.success() {
+        return Ok(None);
+    }
+
+    String::from_utf8_lossy(&output.stdout)
+        .trim()
+        .parse()
+        .context("Failed to parse image size")
+        .map(Some)
+}
+
+async fn retag_source(docker: &Docker, job: &PullJob) -> anyhow::Result<()> {
+    let output = docker
+        .command()
+        .arg("tag")
+        .arg(&job.source)
+        .arg(&job.reference)
+        .output()
+        .await
+        .context("Failed to tag image")?;
+
+    if !output.status.success() {
+        return Err(docker_error(&format!("Tagging {}", job.source), &output));
+    }
+
+    // Removing a digest reference would delete the image we just pulled.
+    if job.source.contains('@') {
+        return Ok(());
+    }
+
+    docker
+        .command()
+        .arg("image")
+        .arg("rm")
+        .arg(&job.source)
+        .status()
+        .await
+        .context("Failed to remove image")?;
+
+    Ok(())
+}
+
+/// Checks a platform like `linux/amd64`.
+pub fn parse_platform(value: &str) -> Result<String, String> {
+    if !value.contains('/') || value.split('/').any(str::is_empty) {
+        return Err(format!(
+            "invalid platform {value:?}, expected os/arch like linux/amd64"
+        ));
+    }
+
+    Ok(value.to_string())
+}
+
+/// Whether two references name the same image, e.g. `redis:7` and `docker.io/library/redis:7`.
+fn same_reference(a: &str, b: &str) -> bool {
+    let normalize = |reference: &str| {
+        parse_image_reference(reference).ok().map(|reference| {
+            let tag = reference.tag.unwrap_or_else(|| "latest".to_string());
+            (reference.registry, reference.library, reference.repo, tag)
+        })
+    };
+
+    a == b || normalize(a).is_some_and(|a| Some(a) == normalize(b))
+}
+
+/// Prefixes the image with the registry, if it is not on Docker Hub.
+pub fn registry_image(registry: Option<&str>, image: String) -> String {
+    match registry {
+        Some(registry) => format!("{}/{image}", registry.trim_end_matches('/')),
+        None => image,
+    }
+}
+
+/// Joins the library and repo, e.g. `bitnami/redis`.
+pub fn image_name(library: Option<impl AsRef<str>>, repo: &str) -> String {
+    if let Some(library) = library {
+        format!("{}/{}", library.as_ref(), repo)
+    } else {
+        repo.to_string()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum TagSource<'a> {
+    DockerHub,
+    Registry(&'a str),
+}
+
+impl<'a> TagSource<'a> {
+    fn from_registry(registry: Option<&'a str>) -> Self {
+        match registry.map(|registry| registry.trim_end_matches('/')) {
+            None | Some("docker.io" | "index.docker.io" | "registry-1.docker.io") => {
+                Self::DockerHub
+            }
+            Some(host) => Self::Registry(host),
+        }
+    }
+}
+
+/// Fetches the tags of an image from Docker Hub or its registry, using the cache of the hub
+/// when it has one.
+pub async fn fetch_tags(
+    hub: &Hub,
+    registry: Option<&str>,
+    library: Option<impl AsRef<str>>,
+    repo: &str,
+    options: FetchOptions,
+    token: Option<&str>,
+) -> Result<FetchTagsResponse, PullatorError> {
+    fetch_tags_from(hub, registry, library, repo, options, token, None).await
+}
+
+/// Continues a [`fetch_tags`] that stopped at the options from the `next` cursor of its
+/// response, fetching the following pages with the same options. Never cached.
+pub async fn fetch_more_tags(
+    hub: &Hub,
+    registry: Option<&str>,
+    library: Option<impl AsRef<str>>,
+    repo: &str,
+    next: &str,
+    options: FetchOptions,
+    token: Option<&str>,
+) -> Result<FetchTagsResponse, PullatorError> {
+    fetch_tags_from(hub, registry, library, repo, options, token, Some(next)).await
+}
+
+async fn fetch_tags_from(
+    hub: &Hub,
+    registry: Option<&str>,
+    library: Option<impl AsRef<str>>,
+    repo: &str,
+    options: FetchOptions,
+    token: Option<&str>,
+    next: Option<&str>,
+) -> Result<FetchTagsResponse, PullatorError> {
+    let library = library.as_ref().map(|s| s.as_ref());
+    let source = TagSource::from_registry(registry);
+
+    let (image, repository) = match source {
+        TagSource::DockerHub => (
+            image_name(library, repo),
+            format!("{}/{}", library.unwrap_or("library"), repo),
+        ),
+        TagSource::Registry(host) => {
+            let image = registry_image(Some(host), image_name(library, repo));
+            (image.clone(), image)
+        }
+    };
+
+    // A continuation alone would look like the complete tag list to the cache.
+    if next.is_none() {
+        if let Some(response) = hub.cached_tags(&repository) {
+            tracing::debug!("Using cached tags for {image}");
+            return Ok(response);
+        }
+    }
+
+    let response = match source {
+        TagSource::DockerHub => {
+            fetch_hub_tags(hub, &image, &repository, options, token, next).await?
+        }
+        TagSource::Registry(host) => {
+            let name = image_name(library, repo);
+            fetch_registry_tags(hub, host, &name, &image, options, next).await?
+        }
+    };
+    if next.is_none() {
+        hub.cache_tags(&repository, &response);
+    }
+
+    Ok(response)
+}
+
+async fn fetch_hub_tags(
+    hub: &Hub,
+    image: &str,
+    repository: &str,
+    options: FetchOptions,
+    token: Option<&str>,
+    next: Option<&str>,
+) -> Result<FetchTagsResponse, PullatorError> {
+    let mut url = Some(next.map_or_else(
+        || {
+            format!(
+                "{}/v2/repositories/{repository}/tags?page_size={}",
+                hub.url,
+                options.page_size()
+            )
+        },
+        String::from,
+    ));
+
+    let mut results = vec![];
+    let mut pages = 0;
+
+    while let Some(cursor) = url.take() {
+        if options.reached(pages, results.len()) {
+            url = Some(cursor);
+            break;
+        }
+
+        let (current, skip) = split_cursor(&cursor);
+        tracing::trace!("fetch_tags URL: {current}");
+
+        let response = match fetch_hub_page(hub, current, image, repository, token).await {
+            Ok(response) => response,
+            Err(error) if options.keep_partial(pages, image, &error) => {
+                url = Some(cursor);
+                break;
+            }
+            Err(error) => return Err(error),
+        };
+
+        pages += 1;
+        let before = results.len();
+        results.extend(response.results.into_iter().skip(skip));
+        url = response.next;
+
+        // Continue within the page, which also keeps the response marked as partial so it
+        // isn't served from the cache.
+        if options.truncate(&mut results) {
+            url = Some(page_cursor(current, skip + results.len() - before));
+            break;
+        }
+    }
+
+    Ok(FetchTagsResponse { results, next: url })
+}
+
+/// The cursor of a page whose first `consumed` tags were already returned, so a fetch
+/// cut short by --max-tags continues right after them.
+fn page_cursor(url: &str, consumed: usize) -> String {
+    format!("{url}#skip={consumed}")
+}
+
+/// Splits a cursor into the page URL and the number of its tags to skip.
+fn split_cursor(cursor: &str) -> (&str, usize) {
+    cursor
+        .rsplit_once("#skip=")
+        .and_then(|(url, skip)| Some((url, skip.parse().ok()?)))
+        .unwrap_or((cursor, 0))
+}
+
+async fn fetch_hub_page(
+    hub: &Hub,
+    url: &str,
+    image: &str,
+    repository: &str,
+    token: Option<&str>,
+) -> Result<FetchTagsResponse, PullatorError> {
+    let response = hub_get(hub, url, token).await.map_err(|error| {
+        PullatorError::Network(hub.request_error(error, &format!("fetch tags for {image}")))
+    })?;
+
+    let status = response.status();
+    if status == reqwest::StatusCode::NOT_FOUND {
+        return Err(PullatorError::NotFound(hub_status_error(
+            status, repository,
+        )));
+    } else if !status.is_success() {
+        return Err(PullatorError::Network(hub_status_error(status, repository)));
+    }
+
+    response
+        .json()
+        .await
+        .with_context(|| format!("Failed to parse response for {image}"))
+        .map_err(PullatorError::Network)
+}
+
+async fn fetch_registry_tags(
+    hub: &Hub,
+    host: &str,
+    name: &str,
+    image: &str,
+    options: FetchOptions,
+    next: Option<&str>,
+) -> Result<FetchTagsResponse, PullatorError> {
+    let mut url = Some(next.map_or_else(
+        || {
+            format!(
+                "https://{host}/v2/{name}/tags/list?n={}",
+                options.page_size()
+            )
+        },
+        String::from,
+    ));
+
+    let mut token = None;
+    let mut results = vec![];
+    let mut pages = 0;
+
+    while let Some(cursor) = url.take() {
+        if options.reached(pages, results.len()) {
+            url = Some(cursor);
+            break;
+        }
+
+        let (current, skip) = split_cursor(&cursor);
+        tracing::trace!("fetch_registry_tags URL: {current}");
+
+        let (response, next) =
+            match fetch_registry_page(hub, host, current, image, &mut token).await {
+                Ok(page) => page,
+                Err(error) if options.keep_partial(pages, image, &error) => {
+                    url = Some(cursor);
+                    break;
+                }
+                Err(error) => return Err(error),
+            };
+
+        pages += 1;
+        let before = results.len();
+        // The tags list carries no digest or platform metadata.
+        results.extend(
+            response
+                .tags
+                .unwrap_or_default()
+                .into_iter()
+                .skip(skip)
+                .map(|name| FetchTagsItem {
+                    name,
+                    images: vec![],
+                    digest: None,
+                    last_updated: None,
+                }),
+        );
+        url = next;
+
+        if options.truncate(&mut results) {
+            url = Some(page_cursor(current, skip + results.len() - before));
+            break;
+        }
+    }
+
+    Ok(FetchTagsResponse { results, next: url })
+}
+
+/// Fetches one page of a registry tag list along with the link to the next page.
+async fn fetch_registry_page(
+    hub: &Hub,
+    host: &str,
+    url: &str,
+    image: &str,
+    token: &mut Option<String>,
+) -> Result<(RegistryTagsResponse, Option<String>), PullatorError> {
+    let request_error = |error| {
+        PullatorError::Network(hub.request_error(error, &format!("fetch tags for {image}")))
+    };
+    let mut response = hub_get(hub, url, token.as_deref())
+        .await
+        .map_err(request_error)?;
+
+    // Registries hand out pull tokens through the challenge of the first request, anonymous
+    // ones unless the host has credentials.
+    if response.status() == reqwest::StatusCode::UNAUTHORIZED && token.is_none() {
+        *token = Some(
+            registry_token(hub, host, &response)
+                .await
+                .map_err(PullatorError::Network)?,
+        );
+        response = hub_get(hub, url, token.as_deref())
+            .await
+            .map_err(request_error)?;
+    }
+
+    let status = response.status();
+    if !status.is_success() {
+        let error =
+            anyhow::anyhow!("unexpected response ({status}) while fetching tags for {image}");
+        return Err(if status == reqwest::StatusCode::NOT_FOUND {
+            PullatorError::NotFound(error)
+        } else {
+            PullatorError::Network(error)
+        });
+    }
+
+    let next = response
+        .headers()
+        .get(reqwest::header::LINK)
+        .and_then(|value| value.to_str().ok())
+        .and_then(next_link)
+        .map(|link| {
+            if link.starts_with('/') {
+                format!("https://{host}{link}")
+            } else {
+                link
+            }
+        });
+
+    let response = response
+        .json()
+        .await
+        .with_context(|| format!("Failed to parse response for {image}"))
+        .map_err(PullatorError::Network)?;
+
+    Ok((response, next))
+}
+
+async fn registry_token(
+    hub: &Hub,
+    host: &str,
+    response: &reqwest::Response,
+) -> anyhow::Result<String> {
+    let challenge = response
+        .headers()
+        .get(reqwest::header::WWW_AUTHENTICATE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .context("Registry requires authentication without offering a bearer token")?;
+
+    let mut realm = None;
+    let mut params = vec![];
+    for part in challenge.split(',') {
+        let Some((key, value)) = part.trim().split_once('=') else {
+            continue;
+        };
+        let value = value.trim_matches('"');
+
+        match key {
+            "realm" => realm = Some(value),
+            "service" | "scope" => params.push((key, value)),
+            _ => {}
+        }
+    }
+
+    let realm = realm.context("Registry token challenge has no realm")?;
+    let url = reqwest::Url::parse_with_params(realm, &params)
+        .context("Failed to build registry token URL")?;
+
+    let mut request = hub.client.get(url);
+    if let Some(credentials) = hub.registries.get(host) {
+        let (username, password) = credentials.resolve(host)?;
+        request = request.basic_auth(username, Some(password));
+    }
+
+    let response = request
+        .send()
+        .await
+        .map_err(|error| hub.request_error(error, "request a registry token"))?;
+
+    let status = response.status();
+    if !status.is_success() {
+        anyhow::bail!("Registry token request failed ({status})");
+    }
+
+    let response: RegistryTokenResponse = response
+        .json()
+        .await
+        .context("Failed to parse registry token")?;
+
+    response
+        .token
+        .or(response.access_token)
+        .context("Registry token response has no token")
+}
+
+fn next_link(header: &str) -> Option<String> {
+    header.split(',').find_map(|link| {
+        let (url, params) = link.split_once(';')?;
+        params.contains("rel=\"next\"").then(|| {
+            url.trim()
+                .trim_start_matches('<')
+                .trim_end_matches('>')
+                .to_string()
+        })
+    })
+}
+
+/// A client for Docker Hub and the registries tags are fetched from.
+#[derive(Debug, Clone)]
+pub struct Hub {
+    client: reqwest::Client,
+    url: String,
+    rate_limit_warned: Arc<AtomicBool>,
+    timeout: Duration,
+    cache_dir: Option<PathBuf>,
+    cache_ttl: Duration,
+    registries: BTreeMap<String, RegistryCredentials>,
+}
+
+impl Hub {
+    /// A client for the Docker Hub API at `url`, without a tag cache until [`Hub::with_cache`].
+    pub fn new(url: &str, timeout: Duration) -> anyhow::Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(timeout)
+            .build()
+            .context("Failed to build HTTP client")?;
+
+        reqwest::Url::parse(url).with_context(|| format!("Invalid Docker Hub URL {url:?}"))?;
+
+        Ok(Self {
+            client,
+            url: url.trim_end_matches('/').to_string(),
+            rate_limit_warned: Arc::default(),
+            timeout,
+            cache_dir: None,
+            cache_ttl: Duration::ZERO,
+            registries: BTreeMap::new(),
+        })
+    }
+
+    /// Authenticates tag requests to the registries with their credentials, keyed by host.
+    pub fn with_credentials(self, registries: BTreeMap<String, RegistryCredentials>) -> Self {
+        Self { registries, ..self }
+    }
+
+    /// Reuses fetched Docker Hub tags for `ttl` from the user's cache directory.
+    pub fn with_cache(self, ttl: Duration) -> Self {
+        let cache_dir = std::env::var_os("XDG_CACHE_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+            .map(|dir| dir.join("docker-pullator").join("tags"));
+
+        Self {
+            cache_dir,
+            cache_ttl: ttl,
+            ..self
+        }
+    }
+
+    fn check_rate_limit(&self, response: &reqwest::Response) {
+        let Some(RateLimit { limit, remaining }) = RateLimit::from_headers(response.headers())
+        else {
+            return;
+        };
+
+        tracing::debug!("Docker Hub rate limit: {remaining} of {limit} requests remaining");
+
+        if remaining < HUB_RATE_LIMIT_WARNING
+            && !self.rate_limit_warned.swap(true, Ordering::SeqCst)
+        {
+            tracing::warn!(
+                "Only {remaining} of {limit} Docker Hub requests remaining, log in for a higher limit"
+            );
+        }
+    }
+
+    fn request_error(&self, error: reqwest::Error, action: &str) -> anyhow::Error {
+        if error.is_timeout() {
+            anyhow::anyhow!(
+                "Timed out after {} trying to {action}, see --http-timeout",
+                humantime::format_duration(self.timeout)
+            )
+        } else {
+            anyhow::Error::new(error).context(format!("Failed to {action}"))
+        }
+    }
+
+    /// Where the tags of `repository` are cached, in a directory of their own per hub URL so a
+    /// mirror's tags never answer for Docker Hub's.
+    fn cache_path(&self, repository: &str) -> Option<PathBuf> {
+        let hub = self
+            .url
+            .trim_end_matches('/')
+            .chars()
+            .map(|char| {
+                if char.is_ascii_alphanumeric() || char == '.' {
+                    char
+                } else {
+                    '_'
+                }
+            })
+            .collect::<String>();
+
+        Some(
+            self.cache_dir
+                .as_ref()?
+                .join(hub)
+                .join(format!("{repository}.json")),
+        )
+    }
+
+    fn cached_tags(&self, repository: &str) -> Option<FetchTagsResponse> {
+        let path = self.cache_path(repository)?;
+        let content = std::fs::read(path).ok()?;
+        let cached: CachedTags = serde_json::from_slice(&content).ok()?;
+
+        let age = SystemTime::now()
+            .duration_since(UNIX_EPOCH + Duration::from_secs(cached.fetched_at))
+            .ok()?;
+
+        // Responses cut short by --max-pages can't answer a full fetch.
+        (age < self.cache_ttl && cached.response.next.is_none()).then_some(cached.response)
+    }
+
+    fn cache_tags(&self, repository: &str, response: &FetchTagsResponse) {
+        let Some(path) = self.cache_path(repository) else {
+            return;
+        };
+
+        let fetched_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let cached = CachedTags {
+            fetched_at,
+            response: response.clone(),
+        };
+
+        let result = path
+            .parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .and_then(|()| std::fs::write(&path, serde_json::to_vec(&cached)?));
+
+        if let Err(error) = result {
+            tracing::warn!("Failed to cache tags in {}: {error}", path.display());
+        }
+    }
+}
+
+/// The pull rate limit reported by Docker Hub.
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimit {
+    pub limit: u64,
+    pub remaining: u64,
+}
+
+impl RateLimit {
+    /// Reads the ratelimit-limit and ratelimit-remaining headers, e.g. `100;w=21600`.
+    fn from_headers(headers: &reqwest::header::HeaderMap) -> Option<Self> {
+        let value = |name: &str| {
+            headers
+                .get(name)?
+                .to_str()
+                .ok()?
+                .split(';')
+                .next()?
+                .trim()
+                .parse()
+                .ok()
+        };
+
+        Some(Self {
+            limit: value("ratelimit-limit")?,
+            remaining: value("ratelimit-remaining")?,
+        })
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CachedTags {
+    fetched_at: u64,
+    response: FetchTagsResponse,
+}
+
+/// The Docker Hub API used unless another URL is given.
+pub const DEFAULT_HUB_URL: &str = "https://hub.docker.com";
+const HUB_RATE_LIMIT_RETRIES: u32 = 5;
+const HUB_RATE_LIMIT_WARNING: u64 = 10;
+const HUB_TRANSIENT_RETRIES: u32 = 2;
+const HUB_RATE_LIMIT_MAX_DELAY: Duration = Duration::from_secs(60);
+
+async fn hub_get(hub: &Hub, url: &str, token: Option<&str>) -> reqwest::Result<reqwest::Response> {
+    let mut attempt = 0;
+
+    loop {
+        let mut request = hub.client.get(url);
+        if let Some(token) = token {
+            request = request.bearer_auth(token);
+        }
+
+        let response = match request.send().await {
+            Ok(response) => response,
+            // Connection blips and timeouts are worth a couple of quick retries.
+            Err(error)
+                if (error.is_connect() || error.is_timeout())
+                    && attempt < HUB_TRANSIENT_RETRIES =>
+            {
+                attempt += 1;
+                retry_transient(url, attempt, &error).await;
+                continue;
+            }
+            Err(error) => return Err(error),
+        };
+
+        hub.check_rate_limit(&response);
+        if response.status().is_server_error() && attempt < HUB_TRANSIENT_RETRIES {
+            attempt += 1;
+            retry_transient(url, attempt, response.status()).await;
+            continue;
+        }
+
+        if response.status() != reqwest::StatusCode::TOO_MANY_REQUESTS
+            || attempt >= HUB_RATE_LIMIT_RETRIES
+        {
+            return Ok(response);
+        }
+
+        let delay = response
+            .headers()
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.trim().parse().ok())
+            .map(Duration::from_secs)
+            .unwrap_or_else(|| Duration::from_secs(2u64.pow(attempt)))
+            .min(HUB_RATE_LIMIT_MAX_DELAY);
+        attempt += 1;
+
+        tracing::warn!(
+            "Rate limited by Docker Hub, retrying in {} (attempt {attempt} of {HUB_RATE_LIMIT_RETRIES})",
+            humantime::format_duration(delay)
+        );
+
+        tokio::time::sleep(delay).await;
+    }
+}
+
+async fn retry_transient(url: &str, attempt: u32, reason: impl std::fmt::Display) {
+    let delay = Duration::from_secs(2u64.pow(attempt - 1));
+    tracing::warn!(
+        "Request to {url} failed ({reason}), retrying in {} (attempt {attempt} of {HUB_TRANSIENT_RETRIES})",
+        humantime::format_duration(delay)
+    );
+
+    tokio::time::sleep(delay).await;
+}
+
+fn hub_status_error(status: reqwest::StatusCode, repository: &str) -> anyhow::Error {
+    use reqwest::StatusCode;
+
+    match status {
+        StatusCode::NOT_FOUND => anyhow::anyhow!("repository {repository} not found on Docker Hub"),
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => anyhow::anyhow!(
+            "access to repository {repository} was denied by Docker Hub ({status}), check your credentials"
+        ),
+        StatusCode::TOO_MANY_REQUESTS => anyhow::anyhow!(
+            "Docker Hub rate limit exceeded while fetching tags for {repository}"
+        ),
+        status if status.is_server_error() => anyhow::anyhow!(
+            "Docker Hub server error ({status}) while fetching tags for {repository}"
+        ),
+        status => anyhow::anyhow!(
+            "unexpected response ({status}) while fetching tags for {repository}"
+        ),
+    }
+}
+
+/// Reads the remaining Docker Hub pull rate limit, returning the account it applies to and the
+/// limit, which is `None` for accounts without one.
+pub async fn rate_limit(
+    hub: &Hub,
+    auth: Option<&Auth>,
+) -> anyhow::Result<(String, Option<RateLimit>)> {
+    let env = |key: &str| std::env::var(key).ok().filter(|value| !value.is_empty());
+    let username =
+        env("DOCKER_HUB_USERNAME").or_else(|| auth.and_then(|auth| auth.username.clone()));
+    let password =
+        env("DOCKER_HUB_PASSWORD").or_else(|| auth.and_then(|auth| auth.password.clone()));
+
+    // Docker's documented way to read the pull limit without using it up.
+    let mut request = hub.client.get(
+        "https://auth.docker.io/token?service=registry.docker.io&scope=repository:ratelimitpreview/test:pull",
+    );
+    if let (Some(username), Some(password)) = (&username, &password) {
+        request = request.basic_auth(username, Some(password));
+    }
+
+    let token: RegistryTokenResponse = request
+        .send()
+        .await
+        .map_err(|error| hub.request_error(error, "request a registry token"))?
+        .error_for_status()
+        .context("Docker Hub rejected the token request")?
+        .json()
+        .await
+        .context("Failed to parse registry token")?;
+    let token = token
+        .token
+        .or(token.access_token)
+        .context("Registry token response has no token")?;
+
+    let response = hub
+        .client
+        .head("https://registry-1.docker.io/v2/ratelimitpreview/test/manifests/latest")
+        .bearer_auth(token)
+        .send()
+        .await
+        .map_err(|error| hub.request_error(error, "read the rate limit"))?;
+
+    let account = username.unwrap_or_else(|| "anonymous".to_string());
+    Ok((account, RateLimit::from_headers(response.headers())))
+}
+
+/// Logs in to Docker Hub with the token or credentials from the environment or the config
+/// auth, or returns `None` to stay anonymous.
+pub async fn hub_token(hub: &Hub, auth: Option<&Auth>) -> anyhow::Result<Option<String>> {
+    let env = |key: &str| std::env::var(key).ok().filter(|value| !value.is_empty());
+
+    let token = env("DOCKER_HUB_TOKEN").or_else(|| auth.and_then(|auth| auth.token.clone()));
+    if token.is_some() {
+        return Ok(token);
+    }
+
+    let username =
+        env("DOCKER_HUB_USERNAME").or_else(|| auth.and_then(|auth| auth.username.clone()));
+    let password =
+        env("DOCKER_HUB_PASSWORD").or_else(|| auth.and_then(|auth| auth.password.clone()));
+
+    match (username, password) {
+        (Some(username), Some(password)) => hub_login(hub, &username, &password).await.map(Some),
+        (None, None) => Ok(None),
+        _ => anyhow::bail!("Docker Hub login requires both a username and a password"),
+    }
+}
+
+async fn hub_login(hub: &Hub, username: &str, password: &str) -> anyhow::Result<String> {
+    tracing::debug!("Logging in to Docker Hub as {username}");
+
+    let response: LoginResponse = hub
+        .client
+        .post(format!("{}/v2/users/login", hub.url))
+        .json(&LoginRequest { username, password })
+        .send()
+        .await
+        .map_err(|error| hub.request_error(error, "log in to Docker Hub"))?
+        .error_for_status()
+        .context("Docker Hub rejected the login")?
+        .json()
+        .await
+        .context("Failed to parse login response")?;
+
+    Ok(response.token)
+}
+
+/// Searches Docker Hub for repositories matching the query, at most `limit` of them.
+pub async fn search(hub: &Hub, query: &str, limit: usize) -> anyhow::Result<Vec<SearchResult>> {
+    let url = reqwest::Url::parse_with_params(
+        &format!("{}/v2/search/repositories/", hub.url),
+        &[("query", query), ("page_size", &limit.max(1).to_string())],
+    )
+    .context("Failed to build search URL")?;
+
+    let response = hub_get(hub, url.as_str(), None)
+        .await
+        .map_err(|error| hub.request_error(error, "search Docker Hub"))?;
+
+    let status = response.status();
+    if !status.is_success() {
+        anyhow::bail!("Docker Hub search failed ({status})");
+    }
+
+    let response: SearchResponse = response
+        .json()
+        .await
+        .context("Failed to parse search response")?;
+
+    Ok(response.results.into_iter().take(limit).collect())
+}
+
+#[derive(Debug, Clone, Copy)]
+enum ConfigFormat {
+    Json,
+    Yaml,
+}
+
+impl ConfigFormat {
+    fn from_path(path: &std::path::Path) -> Self {
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_ascii_lowercase());
+
+        match extension.as_deref() {
+            Some("yaml" | "yml") => Self::Yaml,
+            _ => Self::Json,
+        }
+    }
+}
+
+/// The JSON Schema of the config, for `$schema` references in editors.
+pub fn config_schema() -> serde_json::Value {
+    schemars::schema_for!(Config).to_value()
+}
+
+/// The config file, listing the images to pull as profiles keyed by image name.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub struct Config {
+    /// The JSON Schema of the config for editors, as printed by the schema command.
+    #[serde(rename = "$schema", default, skip_serializing_if = "Option::is_none")]
+    pub schema: Option<String>,
+    /// The format version, older configs are migrated when read. Configs without one are
+    /// version 1.
+    #[serde(default = "first_config_version")]
+    pub version: u32,
+    /// The docker compatible binary to run, e.g. podman.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub docker_bin: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth: Option<Auth>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub policy: Option<Policy>,
+    /// The credentials of registries by host, e.g. `ghcr.io` or `harbor.corp:5000`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub registries: BTreeMap<String, RegistryCredentials>,
+    pub pull_profiles: BTreeMap<String, PullProfile>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            schema: None,
+            version: CONFIG_VERSION,
+            docker_bin: None,
+            auth: None,
+            policy: None,
+            registries: BTreeMap::new(),
+            pull_profiles: BTreeMap::new(),
+        }
+    }
+}
+
+impl Config {
+    /// Checks the profiles for invalid names, tags and patterns, listing every problem.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let component = regex::Regex::new(r"^[a-z0-9]+(?:(?:[._]|__|-+)[a-z0-9]+)*$")
+            .expect("component pattern should be valid");
+        let tag = regex::Regex::new(r"^[A-Za-z0-9_][A-Za-z0-9_.-]{0,127}$")
+            .expect("tag pattern should be valid");
+        let valid_path = |path: &str| path.split('/').all(|part| component.is_match(part));
+
+        let mut problems = vec![];
+        for (name, profile) in &self.pull_profiles {
+            if let Some(registry) = &profile.registry {
+                if registry.is_empty() || registry.contains(['/', ' ']) {
+                    problems.push(format!("{name}: invalid registry {registry:?}"));
+                }
+            }
+
+            if let Some(library) = &profile.library {
+                if !valid_path(library) {
+                    problems.push(format!("{name}: invalid library {library:?}"));
+                }
+            }
+
+            if profile.repo.is_empty() {
+                problems.push(format!("{name}: repo is empty"));
+            } else if !valid_path(&profile.repo) {
+                problems.push(format!("{name}: invalid repo {:?}", profile.repo));
+            }
+
+            if profile.tags.is_empty() && profile.pattern.is_none() {
+                problems.push(format!("{name}: no tags configured"));
+            }
+
+            if let Some(pattern) = &profile.pattern {
+                if let Err(error) = regex::Regex::new(pattern) {
+                    problems.push(format!("{name}: invalid pattern {pattern:?}: {error}"));
+                }
+            }
+
+            for invalid in profile.tags.iter().filter(|name| {
+                !tag.is_match(name) && !is_digest(name) && LatestDirective::parse(name).is_none()
+            }) {
+                problems.push(format!("{name}: invalid tag {invalid:?}"));
+            }
+
+            for pattern in &profile.ignore {
+                if let Err(error) = glob::Pattern::new(pattern) {
+                    problems.push(format!(
+                        "{name}: invalid ignore pattern {pattern:?}: {error}"
+                    ));
+                }
+            }
+
+            for platform in &profile.platforms {
+                if let Err(error) = parse_platform(platform) {
+                    problems.push(format!("{name}: {error}"));
+                }
+            }
+        }
+
+        for (host, credentials) in &self.registries {
+            if host.is_empty() || host.contains(['/', ' ']) {
+                problems.push(format!("registries: invalid host {host:?}"));
+            }
+
+            if credentials.username_env.is_empty() || credentials.password_env.is_empty() {
+                problems.push(format!(
+                    "registries: {host} needs both username_env and password_env"
+                ));
+            }
+        }
+
+        let mut names = HashMap::new();
+        for name in self.pull_profiles.keys() {
+            if let Some(other) = names.insert(name.to_lowercase(), name) {
+                problems.push(format!(
+                    "{other} and {name} only differ by case and pull the same image"
+                ));
+            }
+        }
+
+        if !problems.is_empty() {
+            anyhow::bail!("Invalid config:\n  {}", problems.join("\n  "));
+        }
+
+        Ok(())
+    }
+
+    /// The credentials configured for a registry host.
+    pub fn credentials(&self, host: &str) -> Option<&RegistryCredentials> {
+        self.registries.get(host)
+    }
+
+    /// The number of active tags, which pull, push and clean act on.
+    pub fn tag_count(&self) -> usize {
+        self.pull_profiles
+            .values()
+            .map(|profile| profile.active_tags().count())
+            .sum()
+    }
+}
+
+/// Restricts the images that may be added, pulled and pushed, e.g. in a config shared by a team.
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub struct Policy {
+    /// Prefixes like `library`, `bitnami` or `ghcr.io/acme`, only matching images are allowed.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allow: Vec<String>,
+    /// Prefixes of images that are never allowed, even when they match allow.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deny: Vec<String>,
+}
+
+impl Policy {
+    /// Fails if the image may not be used.
+    pub fn check(
+        &self,
+        registry: Option<&str>,
+        library: Option<&str>,
+        repo: &str,
+    ) -> anyhow::Result<()> {
+        // Official Docker Hub images are matched as library/<repo>, other registries have no
+        // such default.
+        let library = library.filter(|library| !library.is_empty());
+        let path = match (registry, library) {
+            (Some(registry), Some(library)) => format!("{registry}/{library}/{repo}"),
+            (Some(registry), None) => format!("{registry}/{repo}"),
+            (None, library) => format!("{}/{repo}", library.unwrap_or("library")),
+        };
+
+        let matches = |prefix: &&String| {
+            let prefix = prefix.trim_end_matches('/');
+            path == prefix || path.starts_with(&format!("{prefix}/"))
+        };
+
+        if let Some(prefix) = self.deny.iter().find(matches) {
+            anyhow::bail!("{path} is denied by the policy entry {prefix:?}");
+        }
+
+        if !self.allow.is_empty() && !self.allow.iter().any(|prefix| matches(&prefix)) {
+            anyhow::bail!(
+                "{path} is not allowed by the policy, allowed are: {}",
+                self.allow.join(", ")
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Fails if any configured image may not be used.
+    pub fn check_config(&self, config: &Config) -> anyhow::Result<()> {
+        let problems = config
+            .pull_profiles
+            .iter()
+            .filter_map(|(name, profile)| {
+                self.check(
+                    profile.registry.as_deref(),
+                    profile.library.as_deref(),
+                    &profile.repo,
+                )
+                .err()
+                .map(|error| format!("{name}: {error}"))
+            })
+            .collect::<Vec<_>>();
+
+        if !problems.is_empty() {
+            anyhow::bail!(
+                "The config violates the policy:\n  {}",
+                problems.join("\n  ")
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// Names the environment variables holding the credentials of a registry, so the secrets
+/// themselves never end up in the config.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub struct RegistryCredentials {
+    pub username_env: String,
+    pub password_env: String,
+}
+
+impl RegistryCredentials {
+    /// Reads the username and password from the environment.
+    pub fn resolve(&self, host: &str) -> anyhow::Result<(String, String)> {
+        let env = |name: &str| {
+            std::env::var(name)
+                .ok()
+                .filter(|value| !value.is_empty())
+                .with_context(|| {
+                    format!("Credentials for {host}: environment variable {name} is not set")
+                })
+        };
+
+        Ok((env(&self.username_env)?, env(&self.password_env)?))
+    }
+}
+
+/// Docker Hub and registry credentials, overridden by the environment.
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq, JsonSchema)]
+pub struct Auth {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct LoginRequest<'a> {
+    username: &'a str,
+    password: &'a str,
+}
+
+#[derive(Debug, Deserialize)]
+struct LoginResponse {
+    token: String,
+}
+
+/// An image and the tags to pull of it.
+#[derive(
+    Debug, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash, JsonSchema,
+)]
+pub struct PullProfile {
+    /// The registry hosting the image, Docker Hub if `None`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub registry: Option<String>,
+    /// The library or namespace, official Docker Hub images have none.
+    pub library: Option<String>,
+    pub repo: String,
+    /// Tags, `sha256:` digests and `@latest:N` directives.
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// A regex of upstream tags to use as well, matched whenever the tags are resolved.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pattern: Option<String>,
+    /// The platforms to pull, e.g. `linux/arm64`, or the default platform if empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub platforms: Vec<String>,
+    /// The digests tags are pinned to.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub digests: BTreeMap<String, String>,
+    /// Glob patterns of tags that are never pulled, pushed or cleaned.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ignore: Vec<String>,
+    /// Notes on why tags are needed.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub notes: BTreeMap<String, String>,
+}
+
+impl PullProfile {
+    /// The image name including the registry, e.g. `ghcr.io/org/app`.
+    pub fn image(&self) -> String {
+        registry_image(
+            self.registry.as_deref(),
+            image_name(self.library.as_ref(), &self.repo),
+        )
+    }
+
+    /// The reference of a tag, or of a digest like `sha256:...` configured in its place.
+    pub fn reference(&self, tag: &str) -> String {
+        if is_digest(tag) {
+            format!("{}@{tag}", self.image())
+        } else {
+            format!("{}:{tag}", self.image())
+        }
+    }
+
+    /// The digest a tag is pinned to, either through digests or by being a digest itself.
+    pub fn pinned_digest<'a>(&'a self, tag: &'a String) -> Option<&'a String> {
+        if is_digest(tag) {
+            Some(tag)
+        } else {
+            self.digests.get(tag)
+        }
+    }
+
+    /// The tags with the upstream tags matching the pattern added, and with the @latest
+    /// directives replaced by the upstream tags they select if `latest`.
+    pub fn resolve_tags(
+        &self,
+        upstream: &[String],
+        latest: bool,
+    ) -> Result<Vec<String>, PullatorError> {
+        let mut tags = vec![];
+        for tag in &self.tags {
+            let resolved = match LatestDirective::parse(tag).filter(|_| latest) {
+                Some(directive) => {
+                    let resolved = directive.resolve(upstream);
+                    if resolved.is_empty() {
+                        tracing::warn!("No version tags of {} found for {tag}", self.image());
+                    } else {
+                        tracing::info!(
+                            "Resolved {}:{tag} to {}",
+                            self.image(),
+                            resolved.join(", ")
+                        );
+                    }
+
+                    resolved
+                }
+                None => vec![tag.clone()],
+            };
+
+            for tag in resolved {
+                if !tags.contains(&tag) {
+                    tags.push(tag);
+                }
+            }
+        }
+
+        if let Some(pattern) = &self.pattern {
+            let pattern = regex::Regex::new(pattern)
+                .with_context(|| format!("Invalid pattern {pattern:?} of {}", self.image()))
+                .map_err(PullatorError::Config)?;
+            let matching = upstream
+                .iter()
+                .filter(|tag| pattern.is_match(tag) && !self.is_ignored(tag))
+                .collect::<Vec<_>>();
+            if matching.is_empty() {
+                tracing::warn!("No tags of {} match {pattern}", self.image());
+            }
+
+            for tag in matching {
+                if !tags.contains(tag) {
+                    tags.push(tag.clone());
+                }
+            }
+        }
+
+        Ok(tags)
+    }
+
+    /// Adds the tag unless the profile already has it, returning whether it was added.
+    pub fn add_tag(&mut self, tag: String) -> bool {
+        if self.tags.contains(&tag) {
+            return false;
+        }
+
+        self.tags.push(tag);
+        true
+    }
+
+    /// Whether the tag matches one of the ignore patterns.
+    pub fn is_ignored(&self, tag: &str) -> bool {
+        self.ignore.iter().any(|pattern| {
+            glob::Pattern::new(pattern).map_or(pattern == tag, |pattern| pattern.matches(tag))
+        })
+    }
+
+    /// The configured tags without the ignored ones and unresolved @latest directives.
+    pub fn active_tags(&self) -> impl Iterator<Item = &String> {
+        self.tags.iter().filter(|tag| {
+            let reason = if self.is_ignored(tag) {
+                "ignored"
+            } else if LatestDirective::parse(tag).is_some() {
+                "only resolved by pull and push"
+            } else {
+                return true;
+            };
+
+            tracing::debug!("Skipping {}:{tag}: {reason}", self.image());
+            false
+        })
+    }
+
+    fn mirror_image(&self, mirror: &str) -> String {
+        format!(
+            "{}/{}/{}",
+            mirror.trim_end_matches('/'),
+            self.library.as_deref().unwrap_or("library"),
+            self.repo
+        )
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct SearchResponse {
+    results: Vec<SearchResult>,
+}
+
+/// A repository found by [`search`].
+#[derive(Debug, Deserialize)]
+pub struct SearchResult {
+    pub repo_name: String,
+    #[serde(default)]
+    pub short_description: String,
+    #[serde(default)]
+    pub star_count: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct RegistryTagsResponse {
+    tags: Option<Vec<String>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RegistryTokenResponse {
+    token: Option<String>,
+    access_token: Option<String>,
+}
+
+/// The tags fetched by [`fetch_tags`], with the next page URL if the fetch stopped early.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FetchTagsResponse {
+    pub results: Vec<FetchTagsItem>,
+    pub next: Option<String>,
+}
+
+/// A tag and the images published under it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FetchTagsItem {
+    pub name: String,
+    pub images: Vec<FetchTagsImageItem>,
+    pub digest: Option<String>,
+    #[serde(default)]
+    pub last_updated: Option<String>,
+}
+
+impl FetchTagsItem {
+    /// When the tag was last pushed, if the registry tells.
+    pub fn published(&self) -> Option<SystemTime> {
+        humantime::parse_rfc3339_weak(self.last_updated.as_deref()?.trim_end_matches('Z')).ok()
+    }
+
+    /// Whether the tag is published for the os and one of the architectures.
+    pub fn matches_platform(&self, os: Option<&str>, archs: &[String]) -> bool {
+        // Registries without platform metadata can't rule any tag out.
+        if (os.is_none() && archs.is_empty()) || self.images.is_empty() {
+            return true;
+        }
+
+        self.images.iter().any(|image| image.matches(os, archs))
+    }
+
+    /// Whether the tag is published for the platform, always true without platform metadata.
+    pub fn matches_platform_exactly(&self, platform: &str) -> bool {
+        self.images.is_empty() || self.images.iter().any(|image| image.is_platform(platform))
+    }
+
+    /// The compressed size in bytes of the platform's image, if the registry tells.
+    pub fn size(&self, platform: &str) -> Option<u64> {
+        self.images
+            .iter()
+            .find(|image| image.is_platform(platform))?
+            .size
+    }
+
+    /// The digest identifying the pushed content, the platform's own digest when one is given.
+    fn push_digest(&self, platform: Option<&str>) -> Option<&str> {
+        match platform {
+            None => self.digest.as_deref(),
+            Some(platform) => self
+                .images
+                .iter()
+                .find(|image| image.is_platform(platform))
+                .and_then(|image| image.digest.as_deref()),
+        }
+    }
+}
+
+/// The image of one platform of a tag.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FetchTagsImageItem {
+    pub os: String,
+    pub architecture: String,
+    #[serde(default)]
+    pub variant: Option<String>,
+    #[serde(default)]
+    pub digest: Option<String>,
+    /// The compressed size in bytes.
+    #[serde(default)]
+    pub size: Option<u64>,
+}
+
+impl FetchTagsImageItem {
+    /// Matches os/arch[/variant], where a platform without variant matches every variant.
+    fn is_platform(&self, platform: &str) -> bool {
+        let mut parts = platform.split('/');
+        parts.next() == Some(self.os.as_str())
+            && parts.next() == Some(self.architecture.as_str())
+            && parts
+                .next()
+                .is_none_or(|variant| self.variant.as_deref() == Some(variant))
+    }
+
+    /// Whether the image is for the os and one of the architectures.
+    pub fn matches(&self, os: Option<&str>, archs: &[String]) -> bool {
+        os.is_none_or(|os| self.os == os)
+            && (archs.is_empty() || archs.contains(&self.architecture))
+    }
+
+    /// The platform like `linux/arm64/v8`, or `None` for attestations.
+    pub fn platform(&self) -> Option<String> {
+        // Attestation manifests are listed with an unknown platform.
+        if self.os.is_empty() || self.architecture.is_empty() || self.architecture == "unknown" {
+            return None;
+        }
+
+        Some(
+            match self
+                .variant
+                .as_deref()
+                .filter(|variant| !variant.is_empty())
+            {
+                Some(variant) => format!("{}/{}/{variant}", self.os, self.architecture),
+                None => format!("{}/{}", self.os, self.architecture),
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(repo: &str, tags: &[&str]) -> PullProfile {
+        PullProfile {
+            registry: None,
+            library: None,
+            repo: repo.to_string(),
+            tags: tags.iter().map(ToString::to_string).collect(),
+            pattern: None,
+            platforms: vec![],
+            digests: BTreeMap::new(),
+            ignore: vec![],
+            notes: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn migrate_config_upgrades_version_1() {
+        let v1 = serde_json::json!({
+            "pull_profiles": {
+                "redis": { "library": null, "repo": "redis", "tags": ["7"] }
+            }
+        });
+
+        let config: Config = serde_json::from_value(migrate_config(v1).unwrap()).unwrap();
+        assert_eq!(config.version, CONFIG_VERSION);
+        assert_eq!(config.pull_profiles["redis"], profile("redis", &["7"]));
+
+        let written = serde_json::to_value(&config).unwrap();
+        let reread: Config = serde_json::from_value(migrate_config(written).unwrap()).unwrap();
+        assert_eq!(reread, config);
+    }
+
+    #[test]
+    fn schema_defaults_the_version_to_1() {
+        let schema = serde_json::to_value(schemars::schema_for!(Config)).unwrap();
+
+        assert_eq!(schema["properties"]["version"]["default"], 1);
+        assert!(!schema["required"]
+            .as_array()
+            .unwrap()
+            .contains(&serde_json::json!("version")));
+    }
+
+    #[test]
+    fn migrate_config_rejects_newer_versions() {
+        let config = serde_json::json!({ "version": CONFIG_VERSION + 1, "pull_profiles": {} });
+
+        assert!(migrate_config(config).is_err());
+    }
+
+    fn item(name: &str, digest: &str) -> FetchTagsItem {
+        FetchTagsItem {
+            name: name.to_string(),
+            images: vec![],
+            digest: Some(digest.to_string()),
+            last_updated: None,
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn push_renames_targets() {
+        let mut config = Config::default();
+        config
+            .pull_profiles
+            .insert("redis".to_string(), profile("redis", &["7"]));
+        let responses = HashMap::from([(
+            "redis".to_string(),
+            FetchTagsResponse {
+                results: vec![
+                    item("7", "sha256:a"),
+                    item("7.2", "sha256:a"),
+                    item("6", "sha256:b"),
+                ],
+                next: None,
+            },
+        )]);
+        let rename = BTreeMap::from([
+            ("7".to_string(), "stable".to_string()),
+            ("redis:7.2".to_string(), "7-latest".to_string()),
+        ]);
+
+        let jobs = push_jobs(&config, "registry.local:5000", None, &rename, &responses);
+        let targets = jobs
+            .iter()
+            .map(|job| (job.source.as_str(), job.target.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            targets,
+            [
+                ("redis:7", "registry.local:5000/redis:stable"),
+                ("redis:7", "registry.local:5000/redis:7-latest"),
+            ]
+        );
+
+        // A stand-in docker that records its arguments, one command per line.
+        let dir = std::env::temp_dir().join(format!("pullator-push-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let bin = dir.join("docker");
+        let log = dir.join("args");
+        std::fs::write(
+            &bin,
+            format!("#!/bin/sh\necho \"$@\" >> {}\n", log.display()),
+        )
+        .unwrap();
+        let mut permissions = std::fs::metadata(&bin).unwrap().permissions();
+        std::os::unix::fs::PermissionsExt::set_mode(&mut permissions, 0o755);
+        std::fs::set_permissions(&bin, permissions).unwrap();
+
+        let docker = Docker::new(bin.display().to_string(), DockerOutput::Capture);
+        push_image(&docker, &jobs[0], false).await.unwrap();
+
+        let args = std::fs::read_to_string(&log).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            args.lines().collect::<Vec<_>>(),
+            [
+                "tag redis:7 registry.local:5000/redis:stable",
+                "push registry.local:5000/redis:stable",
+                "image rm registry.local:5000/redis:stable",
+            ]
+        );
+    }
+
+    /// Serves `tags` in pages of `page_size` like the Docker Hub API, on a local port.
+    fn serve_tags(tags: &'static [&'static str], page_size: usize) -> String {
+        use std::io::{BufRead, Write};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let base = url.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut request = String::new();
+                std::io::BufReader::new(&stream)
+                    .read_line(&mut request)
+                    .unwrap();
+
+                let page = request
+                    .split_once("page=")
+                    .and_then(|(_, page)| page.split(' ').next()?.parse::<usize>().ok())
+                    .unwrap_or(1);
+                let start = (page - 1) * page_size;
+                let end = (start + page_size).min(tags.len());
+                let results = tags[start..end]
+                    .iter()
+                    .map(|name| serde_json::json!({ "name": name, "images": [], "digest": null }))
+                    .collect::<Vec<_>>();
+                let next = (end < tags.len()).then(|| {
+                    format!(
+                        "{base}/v2/repositories/library/app/tags?page_size={page_size}&page={}",
+                        page + 1
+                    )
+                });
+                let body = serde_json::json!({ "results": results, "next": next }).to_string();
+
+                let _ = write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{body}",
+                    body.len()
+                );
+            }
+        });
+
+        url
+    }
+
+    #[tokio::test]
+    async fn fetch_more_tags_continues_within_a_truncated_page() {
+        const TAGS: &[&str] = &["1", "2", "3", "4", "5", "6", "7"];
+        let hub = Hub::new(&serve_tags(TAGS, 3), Duration::from_secs(5)).unwrap();
+        // Four tags end in the middle of the second page of three.
+        let options = FetchOptions {
+            max_tags: Some(4),
+            page_size: Some(3),
+            ..Default::default()
+        };
+
+        let first = fetch_tags(&hub, None, None::<&str>, "app", options, None)
+            .await
+            .unwrap();
+        let names = |response: &FetchTagsResponse| {
+            response
+                .results
+                .iter()
+                .map(|item| item.name.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(&first), ["1", "2", "3", "4"]);
+
+        let next = first.next.as_deref().unwrap();
+        let rest = fetch_more_tags(&hub, None, None::<&str>, "app", next, options, None)
+            .await
+            .unwrap();
+        assert_eq!(names(&rest), ["5", "6", "7"]);
+        assert_eq!(rest.next, None);
+    }
+
+    fn pull_entry(image: &str, tag: &str, platform: &str, status: ReportStatus) -> ReportEntry {
+        ReportEntry {
+            image: image.to_string(),
+            tag: tag.to_string(),
+            action: ReportAction::Pull,
+            platform: Some(platform.to_string()),
+            registry: None,
+            target: None,
+            status,
+            error: None,
+            error_kind: None,
+        }
+    }
+
+    #[test]
+    fn only_pulled_tags_reach_the_push() {
+        let mut config = Config::default();
+        config.pull_profiles.insert(
+            "redis".to_string(),
+            profile("redis", &["7", "@latest:1", "6"]),
+        );
+        config
+            .pull_profiles
+            .insert("postgres".to_string(), profile("postgres", &["16"]));
+        let report = Report {
+            entries: vec![
+                pull_entry("redis", "7", "linux/amd64", ReportStatus::Pulled),
+                pull_entry("redis", "7.2", "linux/amd64", ReportStatus::Skipped),
+                pull_entry("redis", "6", "linux/amd64", ReportStatus::Pulled),
+                pull_entry("redis", "6", "linux/arm64", ReportStatus::Failed),
+                pull_entry("postgres", "16", "linux/amd64", ReportStatus::Failed),
+            ],
+        };
+
+        let pulled = report.pulled_config(&config);
+        assert_eq!(pulled.pull_profiles.keys().collect::<Vec<_>>(), ["redis"]);
+        assert_eq!(pulled.pull_profiles["redis"].tags, ["7", "7.2"]);
+
+        let responses = HashMap::from([(
+            "redis".to_string(),
+            FetchTagsResponse {
+                results: vec![
+                    item("7", "sha256:a"),
+                    item("7.2", "sha256:b"),
+                    item("6", "sha256:c"),
+                ],
+                next: None,
+            },
+        )]);
+        let jobs = push_jobs(
+            &pulled,
+            "registry.local",
+            None,
+            &BTreeMap::new(),
+            &responses,
+        );
+        let targets = jobs
+            .iter()
+            .map(|job| job.target.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            targets,
+            ["registry.local/redis:7", "registry.local/redis:7.2"]
+        );
+    }
+
+    #[test]
+    fn policy_matches_registry_images_without_library() {
+        let policy = Policy {
+            allow: vec!["ghcr.io/app".to_string(), "library".to_string()],
+            deny: vec!["ghcr.io/app/internal".to_string()],
+        };
+
+        assert!(policy.check(Some("ghcr.io"), None, "app").is_ok());
+        assert!(policy.check(Some("ghcr.io"), Some("app"), "web").is_ok());
+        assert!(policy
+            .check(Some("ghcr.io"), Some("app"), "internal")
+            .is_err());
+        assert!(policy
+            .check(Some("ghcr.io"), Some("library"), "app")
+            .is_err());
+        assert!(policy.check(None, None, "redis").is_ok());
+        assert!(policy.check(None, Some("bitnami"), "redis").is_err());
+    }
+
+    #[test]
+    fn add_tag_skips_existing_tags() {
+        let mut profile = profile("redis", &["7", "6"]);
+
+        assert!(!profile.add_tag("7".to_string()));
+        assert_eq!(profile.tags, ["7", "6"]);
+
+        assert!(profile.add_tag("7.2".to_string()));
+        assert_eq!(profile.tags, ["7", "6", "7.2"]);
+    }
+}
@@ -1,225 +1,2889 @@
 use std::{
-    collections::{BTreeMap, HashMap},
-    process::{Command, Stdio},
+    collections::{BTreeMap, BTreeSet},
+    io::IsTerminal,
+    path::PathBuf,
+    process::ExitCode,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime},
 };
 
 use anyhow::Context;
-use clap::Parser;
-use inquire::{MultiSelect, Select, Text};
-use serde::{Deserialize, Serialize};
+use clap::{CommandFactory, Parser, ValueEnum};
+use docker_pullator::{
+    clean, config_backups, docker_error, fetch_more_tags, fetch_tags, has_digest, hub_token,
+    image_name, is_digest, is_missing_image, latest_tags, local_digests, local_size, local_tags,
+    parse_image_reference, parse_platform, read_config, read_policy, registry_image,
+    registry_login, resolve_digests, resolve_latest, resolve_patterns, write_config, Config,
+    Docker, DockerOutput, FetchOptions, FetchTagsImageItem, FetchTagsItem, FetchTagsResponse, Hub,
+    ImageReference, LatestDirective, MirrorOptions, PlanChange, Policy, Progress, PullOptions,
+    PullProfile, PullatorError, PushOptions, RateLimit, Report, ReportEntry, ReportStatus,
+    TagVersion, DEFAULT_HUB_URL, DRY_RUN_TARGET,
+};
+use inquire::{Confirm, InquireError, MultiSelect, Select, Text};
+use serde::Serialize;
+
+#[derive(Parser)]
+struct Cli {
+    /// The path to the config file, or - to read it from stdin [default: the nearest config.json, then ~/.config/docker-pullator/config.json]
+    #[clap(long)]
+    config: Option<String>,
+
+    /// Read the allowed and denied images from this file instead of the policy in the config
+    #[clap(long, global = true)]
+    policy: Option<String>,
+
+    /// Run even when another process holds the lock on the config
+    #[clap(long, global = true)]
+    force_lock: bool,
+
+    /// Write the changed config to this path instead of back to --config
+    #[clap(long)]
+    output: Option<String>,
+
+    /// Never write the config, e.g. to try out add or edit
+    #[clap(long, global = true)]
+    no_write: bool,
+
+    /// The number of timestamped config backups to keep in .backups next to it, 0 disables them
+    #[clap(long, global = true, default_value_t = 5)]
+    backups: usize,
+
+    /// The docker compatible binary to run, e.g. podman [default: docker]
+    #[clap(long)]
+    docker_bin: Option<String>,
+
+    /// How the output of docker commands is handled
+    #[clap(long, value_enum, default_value_t = DockerOutput::Tee)]
+    docker_output: DockerOutput,
+
+    /// How the progress of docker pull is shown
+    #[clap(long, global = true, value_enum, default_value_t = Progress::Plain)]
+    progress: Progress,
+
+    /// Kill docker commands that run longer than this and treat them as failed, e.g. 30m
+    #[clap(long, global = true, value_parser = humantime::parse_duration)]
+    docker_timeout: Option<Duration>,
+
+    /// How long fetched Docker Hub tags are reused from the cache
+    #[clap(long, global = true, default_value = "1h", value_parser = humantime::parse_duration)]
+    cache_ttl: Duration,
+
+    /// The Docker Hub API to talk to, e.g. a proxy or mock server [env: HUB_URL] [default: https://hub.docker.com]
+    #[clap(long, global = true)]
+    hub_url: Option<String>,
+
+    /// How long a single request to Docker Hub or a registry may take
+    #[clap(long, global = true, default_value = "30s", value_parser = humantime::parse_duration)]
+    http_timeout: Duration,
+
+    /// Always fetch tags from Docker Hub instead of the cache
+    #[clap(long, global = true)]
+    no_cache: bool,
+
+    /// Print a JSON report of pull, push and plan results instead of the docker output
+    #[clap(long, global = true)]
+    json: bool,
+
+    /// Color the list and status tables, auto only colors a terminal without NO_COLOR
+    #[clap(long, global = true, value_enum, default_value_t = ColorChoice::Auto)]
+    color: ColorChoice,
+
+    /// Write pull and push results to this file in the Prometheus textfile format
+    #[clap(long, global = true)]
+    metrics_file: Option<String>,
+
+    /// Only log errors
+    #[clap(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Log more details, can be repeated up to -vvv
+    #[clap(short, long, global = true, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// Fail instead of prompting for missing input, implied when stdin is not a terminal
+    #[clap(long, global = true, alias = "no-prompt")]
+    non_interactive: bool,
+
+    #[clap(subcommand)]
+    subcommand: SubCommand,
+}
+
+#[derive(Parser)]
+enum SubCommand {
+    /// Add new images and tags to the config
+    Add(Box<AddCommand>),
+    /// Pull images from the config
+    Pull(PullCommand),
+    /// Clean images listed in the config
+    Clean(CleanCommand),
+    /// Remove local tags of configured images that are no longer in the config
+    Prune(PruneCommand),
+    /// Edit images and tags in the config
+    Edit,
+    /// Push images to a registry
+    Push(PushCommand),
+    /// Pull and push
+    Sync(SyncCommand),
+    /// Copy images straight from their source to a registry with skopeo
+    Mirror(MirrorCommand),
+    /// List images and tags in the config
+    List(ListCommand),
+    /// Remove images or tags from the config
+    Remove(RemoveCommand),
+    /// Print a shell completion script
+    Completions(CompletionsCommand),
+    /// Show which configured images are present locally
+    Status,
+    /// Show which platforms the configured tags are published for
+    ArchReport(ArchReportCommand),
+    /// Show the disk space of the local tags and the download size of the missing ones
+    Du(DuCommand),
+    /// Search Docker Hub for images
+    Search(SearchCommand),
+    /// Check the config for mistakes
+    Validate,
+    /// Restore the config from one of its backups
+    Restore(RestoreCommand),
+    /// Print the JSON Schema of the config
+    Schema,
+    /// Save the configured images into a tarball
+    Export(ExportCommand),
+    /// Load images from a tarball created by export
+    Import(ImportCommand),
+    /// Check that docker, Docker Hub and the config are usable
+    Doctor,
+    /// Check the configured tags against the upstream tag lists
+    Refresh(RefreshCommand),
+    /// Show the remaining Docker Hub pull rate limit
+    RateLimit,
+    /// Show what a sync would change without pulling or removing anything
+    Plan(PlanCommand),
+}
+
+impl SubCommand {
+    /// Whether the command may write the config, and so has to hold its lock.
+    fn changes_config(&self) -> bool {
+        match self {
+            Self::Add(_) | Self::Edit | Self::Remove(_) | Self::Refresh(_) => true,
+            Self::Restore(command) => !command.list,
+            Self::Pull(command) => command.resolve,
+            Self::Search(command) => command.add,
+            _ => false,
+        }
+    }
+
+    /// Whether the command runs docker, and so has to find it first.
+    fn uses_docker(&self) -> bool {
+        matches!(
+            self,
+            Self::Pull(_)
+                | Self::Clean(_)
+                | Self::Prune(_)
+                | Self::Push(_)
+                | Self::Sync(_)
+                | Self::Status
+                | Self::Du(_)
+                | Self::Export(_)
+                | Self::Import(_)
+                | Self::Plan(_)
+        )
+    }
+}
+
+#[derive(Parser, Default, Clone)]
+struct AddCommand {
+    /// The full image reference, e.g. nginx, bitnami/redis:7 or ghcr.io/org/app
+    #[clap(long, value_parser = parse_image_reference, conflicts_with_all = ["registry", "library", "repo"])]
+    image: Option<ImageReference>,
+
+    /// The registry hosting the image, e.g. ghcr.io [default: Docker Hub]
+    #[clap(long)]
+    registry: Option<String>,
+
+    /// The library to pull the images from, lowercased like docker image references
+    #[clap(short, long)]
+    library: Option<String>,
+
+    /// The name of the image to pull, lowercased like docker image references
+    #[clap(short, long)]
+    repo: Option<String>,
+
+    /// The tags to pull, or @latest:N[:suffix] to pull the newest N version tags at pull time
+    #[clap(short, long)]
+    tags: Option<Vec<String>>,
+
+    /// Read newline separated tags from this file, - for stdin
+    #[clap(long, conflicts_with_all = ["tags", "latest"])]
+    tags_file: Option<PathBuf>,
+
+    /// Add every name:tag reference of this file, one per line with # comments
+    #[clap(long, conflicts_with_all = ["image", "registry", "library", "repo", "tags", "tags_file", "latest", "repeat"])]
+    from_file: Option<PathBuf>,
+
+    /// Accept tags from --tags-file or --from-file without checking they exist
+    #[clap(long)]
+    no_verify: bool,
+
+    #[clap(flatten)]
+    fetch: FetchOptions,
+
+    /// Never pull, push or clean tags matching this pattern, can be repeated
+    #[clap(long)]
+    ignore: Vec<String>,
+
+    /// A note stored with the added tags, e.g. why they are needed
+    #[clap(long)]
+    note: Option<String>,
+
+    /// The order in which tags are offered
+    #[clap(long, value_enum, default_value_t = TagSort::NameDesc)]
+    sort: TagSort,
+
+    /// Only offer tags available for this architecture, can be repeated
+    #[clap(long)]
+    arch: Vec<String>,
+
+    /// Only offer tags available for this operating system
+    #[clap(long)]
+    os: Option<String>,
+
+    /// Store every platform the selected tags are published for, narrowed by --os and --arch
+    #[clap(long)]
+    all_platforms: bool,
+
+    /// Pin the tags to their current digest
+    #[clap(long)]
+    pin: bool,
+
+    /// Store the current digests of the selected tags in place of the tag names
+    #[clap(long, conflicts_with_all = ["pin", "track"])]
+    as_digest: bool,
+
+    /// Only offer tags matching this regex, unanchored unless it uses ^ and $
+    #[clap(long, conflicts_with = "glob")]
+    filter: Option<String>,
+
+    /// Only offer tags matching this shell-style pattern, e.g. '15.*'
+    #[clap(long)]
+    glob: Option<String>,
+
+    /// Only offer tags published since this date or duration ago, e.g. 2024-01-31 or 30d
+    #[clap(long, value_parser = parse_date)]
+    since: Option<SystemTime>,
+
+    /// Only offer tags published before this date or duration ago, e.g. 2024-06-30 or 7d
+    #[clap(long, value_parser = parse_date)]
+    until: Option<SystemTime>,
+
+    /// Keep tags without a publish date when filtering with --since or --until
+    #[clap(long)]
+    include_undated: bool,
+
+    /// Select the newest N version tags instead of prompting
+    #[clap(long, conflicts_with = "tags")]
+    latest: Option<usize>,
+
+    /// The variant suffix for --latest, e.g. alpine for 1.2.3-alpine
+    #[clap(long, requires = "latest")]
+    suffix: Option<String>,
+
+    /// Store --latest as a @latest:N directive that pull resolves each time instead of fixed tags
+    #[clap(long, requires = "latest", conflicts_with = "pin")]
+    track: bool,
+
+    /// Also use every upstream tag matching this regex, resolved whenever the config is used from the tag list cached for --cache-ttl
+    #[clap(long, conflicts_with_all = ["latest", "as_digest"])]
+    pattern: Option<String>,
+
+    /// Ask for another image after each one, saving all of them at the end
+    #[clap(long)]
+    repeat: bool,
+
+    /// Offer the tags N at a time with a choice to load more, only fetching the pages shown
+    #[clap(long, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    page: Option<usize>,
+}
+
+#[derive(Parser)]
+struct PullCommand {
+    #[clap(flatten)]
+    options: PullOptions,
+
+    #[clap(flatten)]
+    scope: PullScope,
+
+    /// Refresh the pinned digests from Docker Hub before pulling
+    #[clap(long)]
+    resolve: bool,
+
+    #[clap(flatten)]
+    selection: ProfileSelection,
+
+    /// Print the docker commands instead of running them
+    #[clap(long)]
+    dry_run: bool,
+}
+
+#[derive(Parser, Clone, Default)]
+struct PullScope {
+    /// Only pull images from this library
+    #[clap(short, long)]
+    library: Option<String>,
+
+    /// Only pull this image
+    #[clap(short, long)]
+    repo: Option<String>,
+
+    /// Only pull these configured tags, comma separated or repeated
+    #[clap(short, long, value_delimiter = ',')]
+    tags: Option<Vec<String>>,
+}
+
+impl PullScope {
+    fn is_empty(&self) -> bool {
+        self.library.is_none() && self.repo.is_none() && self.tags.is_none()
+    }
+
+    fn apply(&self, config: &Config) -> anyhow::Result<Config> {
+        if self.is_empty() {
+            return Ok(config.clone());
+        }
+
+        let mut config = config.clone();
+        config.pull_profiles.retain(|_, profile| {
+            // Official Docker Hub images are in the library namespace, like in the policy.
+            let library = profile
+                .library
+                .as_deref()
+                .filter(|library| !library.is_empty());
+            let normalized = library
+                .or(profile.registry.is_none().then_some("library"))
+                .unwrap_or_default();
+            self.library.as_deref().is_none_or(|wanted| {
+                wanted == normalized || (wanted.is_empty() && library.is_none())
+            }) && self
+                .repo
+                .as_deref()
+                .is_none_or(|wanted| wanted == profile.repo)
+        });
+
+        if let Some(tags) = &self.tags {
+            for profile in config.pull_profiles.values_mut() {
+                profile.tags.retain(|tag| tags.contains(tag));
+            }
+            config
+                .pull_profiles
+                .retain(|_, profile| !profile.tags.is_empty());
+        }
+
+        if config.pull_profiles.is_empty() {
+            anyhow::bail!("No configured images match the given --library, --repo and --tags");
+        }
+
+        Ok(config)
+    }
+}
+
+#[derive(Parser, Clone, Default)]
+struct ProfileSelection {
+    /// Only use images whose config key matches this pattern, can be repeated
+    #[clap(long)]
+    include: Vec<String>,
+
+    /// Skip images whose config key matches this pattern, wins over --include
+    #[clap(long)]
+    exclude: Vec<String>,
+}
+
+impl ProfileSelection {
+    fn apply(&self, config: &Config) -> anyhow::Result<Config> {
+        if self.include.is_empty() && self.exclude.is_empty() {
+            return Ok(config.clone());
+        }
+
+        let patterns = |patterns: &[String]| {
+            patterns
+                .iter()
+                .map(|pattern| {
+                    glob::Pattern::new(pattern)
+                        .with_context(|| format!("Invalid image pattern {pattern:?}"))
+                })
+                .collect::<anyhow::Result<Vec<_>>>()
+        };
+        let include = patterns(&self.include)?;
+        let exclude = patterns(&self.exclude)?;
+
+        let mut config = config.clone();
+        config.pull_profiles.retain(|name, _| {
+            (include.is_empty() || include.iter().any(|pattern| pattern.matches(name)))
+                && !exclude.iter().any(|pattern| pattern.matches(name))
+        });
+
+        if config.pull_profiles.is_empty() {
+            anyhow::bail!("No configured images match the given --include and --exclude");
+        }
+
+        Ok(config)
+    }
+}
+
+#[derive(Parser)]
+struct CleanCommand {
+    /// Remove the images even if they are used by a container
+    #[clap(short, long)]
+    force: bool,
+
+    /// Remove the images without asking for confirmation
+    #[clap(short, long)]
+    yes: bool,
+
+    /// Remove pinned tags by their configured digest, leaving re-pointed tags alone
+    #[clap(long)]
+    only_configured_digests: bool,
+
+    #[clap(flatten)]
+    selection: ProfileSelection,
+
+    /// Print the docker commands instead of running them
+    #[clap(long)]
+    dry_run: bool,
+}
+
+#[derive(Parser)]
+struct PruneCommand {
+    /// Remove the images without asking for confirmation
+    #[clap(short, long)]
+    yes: bool,
+
+    /// Print the docker commands instead of running them
+    #[clap(long)]
+    dry_run: bool,
+}
+
+#[derive(Parser)]
+struct PushCommand {
+    /// The registry to push the images to, can be repeated and expands $VAR and ${VAR}
+    #[clap(short, long, required = true, value_parser = expand_env)]
+    registry: Vec<String>,
+
+    /// Clean after push
+    #[clap(short, long)]
+    clean: bool,
+
+    /// Clean without asking for confirmation
+    #[clap(short, long)]
+    yes: bool,
+
+    /// Keep the pushed registry tags locally instead of removing them after the push
+    #[clap(long)]
+    keep_local: bool,
+
+    /// The number of images to push at the same time
+    #[clap(long, default_value_t = 3)]
+    concurrency: usize,
+
+    /// Run docker login for every registry before pushing
+    #[clap(long)]
+    login: bool,
+
+    /// Only push this platform of multi-arch images, e.g. linux/arm64
+    #[clap(long, value_parser = parse_platform)]
+    platform: Option<String>,
+
+    /// Copy the whole manifest list of every tag to the registry, needs the docker buildx plugin
+    #[clap(long, conflicts_with_all = ["platform", "keep_local"])]
+    multi_arch: bool,
+
+    /// Push a tag under another name as SRC=DST, where SRC is a tag or image:tag, can be repeated; image:tag wins over tag and the last --map for the same SRC wins
+    #[clap(long = "map", value_name = "SRC=DST", value_parser = parse_tag_mapping)]
+    rename: Vec<(String, String)>,
+
+    #[clap(flatten)]
+    selection: ProfileSelection,
+
+    /// Print the docker commands instead of running them
+    #[clap(long)]
+    dry_run: bool,
+}
+
+#[derive(Parser)]
+#[clap(after_help = "Exit codes:
+  0  pull and push succeeded
+  1  sync could not run, e.g. because of an invalid config
+  3  the pull phase failed
+  4  the push phase failed
+  5  both phases failed, only with --continue-on-error")]
+struct SyncCommand {
+    /// The registry to push the images to, can be repeated and expands $VAR and ${VAR}
+    #[clap(short, long, required = true, value_parser = expand_env)]
+    registry: Vec<String>,
+
+    /// Push the images that could be pulled even if some pulls failed
+    #[clap(long)]
+    continue_on_error: bool,
+
+    /// Clean after push
+    #[clap(short, long)]
+    clean: bool,
+
+    /// Clean without asking for confirmation
+    #[clap(short, long)]
+    yes: bool,
+
+    /// The number of images to push at the same time
+    #[clap(long, default_value_t = 3)]
+    push_concurrency: usize,
+
+    /// Run docker login for every registry before pulling
+    #[clap(long)]
+    login: bool,
+
+    /// Keep running and sync again after this interval, re-reading the config each time
+    #[clap(long, value_parser = humantime::parse_duration)]
+    watch: Option<Duration>,
+
+    /// Copy the whole manifest list of every tag to the registry, needs the docker buildx plugin
+    #[clap(long)]
+    multi_arch: bool,
+
+    #[clap(flatten)]
+    pull: PullOptions,
+
+    #[clap(flatten)]
+    selection: ProfileSelection,
+
+    /// Print the docker commands instead of running them
+    #[clap(long)]
+    dry_run: bool,
+}
+
+#[derive(Parser)]
+struct MirrorCommand {
+    /// The registry to copy the images to, can be repeated and expands $VAR and ${VAR}
+    #[clap(short, long, required = true, value_parser = expand_env)]
+    registry: Vec<String>,
+
+    /// The number of images to copy at the same time
+    #[clap(long, default_value_t = 3)]
+    concurrency: usize,
+
+    /// Only copy this platform of multi-arch images, e.g. linux/arm64
+    #[clap(long, value_parser = parse_platform)]
+    platform: Option<String>,
+
+    /// The skopeo binary to copy with, when it is missing the images are pulled and pushed
+    #[clap(long, default_value = "skopeo")]
+    skopeo_bin: String,
+
+    #[clap(flatten)]
+    selection: ProfileSelection,
+
+    /// Print the commands instead of running them
+    #[clap(long)]
+    dry_run: bool,
+}
+
+#[derive(Parser)]
+struct RefreshCommand {
+    /// Remove tags that no longer exist upstream from the config
+    #[clap(long)]
+    remove_missing: bool,
+}
+
+#[derive(Parser)]
+struct ArchReportCommand {
+    /// The platform to expect for profiles without their own platforms, can be repeated
+    #[clap(long, value_parser = parse_platform)]
+    platform: Vec<String>,
+
+    /// Fail when a tag is missing an expected platform
+    #[clap(long)]
+    strict: bool,
+
+    #[clap(flatten)]
+    selection: ProfileSelection,
+}
+
+#[derive(Parser)]
+struct DuCommand {
+    /// The platform to size missing tags of profiles without their own platforms for [default: this host]
+    #[clap(long, value_parser = parse_platform)]
+    platform: Option<String>,
+
+    #[clap(flatten)]
+    selection: ProfileSelection,
+}
+
+#[derive(Parser)]
+struct PlanCommand {
+    #[clap(flatten)]
+    selection: ProfileSelection,
+}
+
+#[derive(Parser)]
+struct RemoveCommand {
+    /// The registry of the image to remove, if it is not on Docker Hub
+    #[clap(long, conflicts_with = "all")]
+    registry: Option<String>,
+
+    /// The library of the image to remove
+    #[clap(short, long, conflicts_with = "all")]
+    library: Option<String>,
+
+    /// The name of the image to remove
+    #[clap(short, long, required_unless_present = "all")]
+    repo: Option<String>,
+
+    /// The tags to remove, removes the whole image if omitted
+    #[clap(short, long, conflicts_with = "all")]
+    tags: Option<Vec<String>>,
+
+    /// Remove every image from the config
+    #[clap(long, conflicts_with = "repo")]
+    all: bool,
+}
+
+#[derive(Parser)]
+struct RestoreCommand {
+    /// The backup to restore, its number in --list or its file name [default: pick one]
+    backup: Option<String>,
+
+    /// List the backups, newest first, instead of restoring one
+    #[clap(long, conflicts_with = "backup")]
+    list: bool,
+
+    /// Restore without asking for confirmation
+    #[clap(short, long)]
+    yes: bool,
+}
+
+#[derive(Parser)]
+struct CompletionsCommand {
+    /// The shell to generate completions for
+    shell: clap_complete::Shell,
+}
+
+#[derive(Parser)]
+struct SearchCommand {
+    /// The text to search for
+    query: String,
+
+    /// The maximum number of results to show
+    #[clap(short, long, default_value_t = 10)]
+    limit: usize,
+
+    /// Choose one of the results and add it to the config
+    #[clap(long)]
+    add: bool,
+}
+
+#[derive(Parser)]
+struct ExportCommand {
+    /// The path of the tarball to write
+    #[clap(short, long)]
+    output: String,
+
+    /// Pull images that are not present locally before exporting
+    #[clap(long)]
+    pull_missing: bool,
+
+    #[clap(flatten)]
+    pull: PullOptions,
+
+    /// Print the docker commands instead of running them
+    #[clap(long)]
+    dry_run: bool,
+}
+
+#[derive(Parser)]
+struct ImportCommand {
+    /// The path of the tarball to load
+    #[clap(short, long)]
+    input: String,
+
+    /// Print the docker commands instead of running them
+    #[clap(long)]
+    dry_run: bool,
+}
+
+#[derive(Parser)]
+struct ListCommand {
+    /// The output format
+    #[clap(short, long, value_enum, default_value_t = ListFormat::Plain)]
+    format: ListFormat,
+}
+
+#[derive(Debug, Default, Clone, Copy, ValueEnum)]
+enum TagSort {
+    NameAsc,
+    #[default]
+    NameDesc,
+    /// Most recently pushed first
+    DateDesc,
+    /// Highest version first, e.g. 10 before 9
+    SemverDesc,
+}
+
+impl TagSort {
+    fn sort(self, items: &mut [FetchTagsItem]) {
+        match self {
+            Self::NameAsc => items.sort_by(|a, b| a.name.cmp(&b.name)),
+            Self::NameDesc => items.sort_by(|a, b| b.name.cmp(&a.name)),
+            // RFC 3339 timestamps sort chronologically as strings.
+            Self::DateDesc => items.sort_by(|a, b| {
+                b.last_updated
+                    .cmp(&a.last_updated)
+                    .then_with(|| b.name.cmp(&a.name))
+            }),
+            Self::SemverDesc => items.sort_by_cached_key(|item| {
+                std::cmp::Reverse((TagVersion::parse(&item.name), item.name.clone()))
+            }),
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ListFormat {
+    Plain,
+    Json,
+    Table,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    fn enabled(self) -> bool {
+        match self {
+            Self::Auto => std::io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none(),
+            Self::Always => true,
+            Self::Never => false,
+        }
+    }
+}
+
+/// Wraps the text in an ANSI style like `1` for bold or `31` for red, if colors are on.
+fn paint(text: &str, style: &str, color: bool) -> String {
+    if color {
+        format!("\x1b[{style}m{text}\x1b[0m")
+    } else {
+        text.to_string()
+    }
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> ExitCode {
+    match run().await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("Error: {error:?}");
+
+            let code = error
+                .downcast_ref::<ExitError>()
+                .map_or(1, |error| error.code);
+            ExitCode::from(code)
+        }
+    }
+}
+
+async fn run() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+    init_logging(&cli);
+
+    if let SubCommand::Completions(command) = &cli.subcommand {
+        completions(command);
+        return Ok(());
+    }
+
+    if let SubCommand::Doctor = &cli.subcommand {
+        return doctor(&cli).await;
+    }
+
+    if let SubCommand::Schema = &cli.subcommand {
+        let schema = serde_json::to_string_pretty(&docker_pullator::config_schema())
+            .context("Failed to serialize schema")?;
+        println!("{schema}");
+        return Ok(());
+    }
+
+    let config_path = config_path(cli.config.as_deref());
+    // A config from stdin can't be written back, only to --output.
+    let from_stdin = config_path == "-";
+    // Held until the changed config is written, read-only commands don't take it.
+    let _lock =
+        if cli.subcommand.changes_config() && !cli.force_lock && !cli.no_write && !from_stdin {
+            Some(lock_config(&config_path)?)
+        } else {
+            None
+        };
+
+    // The config itself may be the broken file to restore.
+    if let SubCommand::Restore(command) = &cli.subcommand {
+        if from_stdin {
+            anyhow::bail!("restore needs the path of the config, not -");
+        }
+        return restore(&cli, &config_path, command);
+    }
+
+    let mut config = read_config(&config_path)?;
+    config.validate()?;
+
+    if let SubCommand::Validate = &cli.subcommand {
+        println!("{config_path} is valid");
+        return Ok(());
+    }
+
+    let docker = Docker::new(
+        cli.docker_bin
+            .clone()
+            .or_else(|| config.docker_bin.clone())
+            .unwrap_or_else(|| "docker".to_string()),
+        // Docker output would corrupt the JSON report on stdout.
+        if cli.json {
+            DockerOutput::Capture
+        } else {
+            cli.docker_output
+        },
+    )
+    .with_timeout(cli.docker_timeout)
+    .with_progress(cli.progress);
+
+    if cli.subcommand.uses_docker() {
+        docker.check_installed().await?;
+    }
+
+    let interactive = !cli.non_interactive && std::io::stdin().is_terminal();
+    let color = !cli.json && cli.color.enabled();
+    let mut hub =
+        Hub::new(&hub_url(&cli), cli.http_timeout)?.with_credentials(config.registries.clone());
+    if !cli.no_cache {
+        hub = hub.with_cache(cli.cache_ttl);
+    }
+    let mut report = Report::default();
+    let policy = load_policy(&cli, &config)?;
+
+    if matches!(
+        cli.subcommand,
+        SubCommand::Pull(_) | SubCommand::Push(_) | SubCommand::Sync(_) | SubCommand::Mirror(_)
+    ) {
+        policy.check_config(&config)?;
+    }
+
+    // Watching handles Ctrl-C itself, so the running cycle can finish.
+    if let SubCommand::Sync(command) = &cli.subcommand {
+        if let Some(interval) = command.watch {
+            if from_stdin {
+                anyhow::bail!(
+                    "sync --watch rereads the config every cycle, so it can't be read from stdin"
+                );
+            }
+            let docker = docker.with_dry_run(command.dry_run);
+            return watch(&cli, &config_path, &hub, &docker, command, interval).await;
+        }
+    }
+
+    let output_path = cli.output.clone().unwrap_or_else(|| config_path.clone());
+    // Unchanged configs are left alone, unless the user asked for a copy elsewhere.
+    let original = config.clone();
+    let dirty = |config: &Config| *config != original || output_path != config_path;
+    let no_write = cli.no_write || (from_stdin && cli.output.is_none());
+    let saves_on_interrupt = matches!(cli.subcommand, SubCommand::Add(_) | SubCommand::Edit);
+    let command = async {
+        match cli.subcommand {
+            SubCommand::Add(command) if command.from_file.is_some() => {
+                add_from_file(&mut config, &hub, &policy, &command).await?
+            }
+            SubCommand::Add(command) if command.repeat => {
+                add_repeatedly(&mut config, &hub, &policy, &command, interactive).await?
+            }
+            SubCommand::Add(command) => {
+                add(&mut config, &hub, &policy, &command, interactive).await?
+            }
+            SubCommand::Pull(command) => {
+                if command.resolve {
+                    resolve_digests(&mut config, &hub).await?;
+                }
+
+                let result = pull(
+                    &config,
+                    &hub,
+                    &docker.with_dry_run(command.dry_run),
+                    &command,
+                    &mut report,
+                )
+                .await
+                .map_err(Into::into);
+                finish_report(&report, cli.json, cli.metrics_file.as_deref(), result)?
+            }
+            SubCommand::Clean(command) => {
+                let selected = resolve_patterns(&command.selection.apply(&config)?, &hub).await?;
+                if command.dry_run
+                    || confirm_removal(selected.tag_count(), command.yes, interactive)?
+                {
+                    clean(
+                        &selected,
+                        &docker.with_dry_run(command.dry_run),
+                        command.force,
+                        command.only_configured_digests,
+                    )
+                    .await?
+                }
+            }
+            SubCommand::Prune(command) => {
+                prune(
+                    // Tags pulled through @latest directives must survive the prune.
+                    &resolve_latest(&config, &hub).await?,
+                    &docker.with_dry_run(command.dry_run),
+                    &command,
+                    interactive,
+                )
+                .await?
+            }
+            SubCommand::Edit => edit(&mut config, &hub, interactive).await?,
+            SubCommand::Push(command) => {
+                let result = push(
+                    &config,
+                    &hub,
+                    &docker.with_dry_run(command.dry_run),
+                    &command,
+                    interactive,
+                    &mut report,
+                )
+                .await
+                .map_err(Into::into);
+                finish_report(&report, cli.json, cli.metrics_file.as_deref(), result)?
+            }
+            SubCommand::Sync(command) => {
+                let result = sync(
+                    &config,
+                    &hub,
+                    &docker.with_dry_run(command.dry_run),
+                    &command,
+                    interactive,
+                    &mut report,
+                )
+                .await;
+                finish_report(&report, cli.json, cli.metrics_file.as_deref(), result)?
+            }
+            SubCommand::Mirror(command) => {
+                let result = mirror(
+                    &config,
+                    &hub,
+                    &docker.with_dry_run(command.dry_run),
+                    &command,
+                    &mut report,
+                )
+                .await;
+                finish_report(&report, cli.json, cli.metrics_file.as_deref(), result)?
+            }
+            SubCommand::List(command) => {
+                list(&resolve_patterns(&config, &hub).await?, &command, color).await?
+            }
+            SubCommand::Remove(command) => remove(&mut config, &command).await?,
+            SubCommand::Status => status(&config, &hub, &docker, color).await?,
+            SubCommand::ArchReport(command) => arch_report(&config, &hub, &command).await?,
+            SubCommand::Du(command) => du(&config, &hub, &docker, &command).await?,
+            SubCommand::Refresh(command) => refresh(&mut config, &hub, &command).await?,
+            SubCommand::RateLimit => rate_limit(&config, &hub).await?,
+            SubCommand::Plan(command) => {
+                plan(&command.selection.apply(&config)?, &hub, &docker, cli.json).await?
+            }
+            SubCommand::Search(command) => {
+                search(&mut config, &hub, &policy, &command, interactive).await?
+            }
+            SubCommand::Export(command) => {
+                export(
+                    &config,
+                    &hub,
+                    &docker.with_dry_run(command.dry_run),
+                    &command,
+                )
+                .await?
+            }
+            SubCommand::Import(command) => {
+                import(&docker.with_dry_run(command.dry_run), &command).await?
+            }
+            SubCommand::Completions(_) => unreachable!("completions don't need the config"),
+            SubCommand::Validate => unreachable!("validate returns before running a command"),
+            SubCommand::Doctor => unreachable!("doctor runs before the config is read"),
+            SubCommand::Schema => unreachable!("schema doesn't need the config"),
+            SubCommand::Restore(_) => unreachable!("restore returns before reading the config"),
+        }
+
+        anyhow::Ok(())
+    };
+
+    // Dropping the command on Ctrl-C stops it from spawning further docker commands.
+    let finished = tokio::select! {
+        result = command => Some(result),
+        _ = tokio::signal::ctrl_c() => None,
+    };
+
+    // Prompts read Ctrl-C as a key press and fail with OperationInterrupted instead.
+    let finished = finished.filter(|result| {
+        !matches!(
+            result
+                .as_ref()
+                .err()
+                .and_then(|error| error.downcast_ref::<InquireError>()),
+            Some(InquireError::OperationInterrupted)
+        )
+    });
+
+    let Some(result) = finished else {
+        if saves_on_interrupt && !no_write && dirty(&config) {
+            config.validate()?;
+            write_config(&output_path, &config, cli.backups)?;
+            eprintln!("Interrupted, saved the changes made so far to {output_path}");
+        } else {
+            eprintln!("Interrupted, stopping the running docker commands");
+        }
+
+        return Err(ExitError::new(130, anyhow::anyhow!("Interrupted")).into());
+    };
+
+    result?;
+
+    if no_write {
+        if config != original {
+            config.validate()?;
+            if cli.no_write {
+                tracing::info!("config changes not persisted (--no-write)");
+            } else {
+                tracing::warn!(
+                    "config changes not persisted, pass --output to save a config read from stdin"
+                );
+            }
+        }
+    } else if dirty(&config) {
+        config.validate()?;
+        write_config(&output_path, &config, cli.backups)?;
+    }
+
+    Ok(())
+}
+
+fn init_logging(cli: &Cli) {
+    use tracing_subscriber::filter::{EnvFilter, LevelFilter};
+
+    let level = match (cli.quiet, cli.verbose) {
+        (true, _) => LevelFilter::ERROR,
+        (false, 0) => LevelFilter::WARN,
+        (false, 1) => LevelFilter::INFO,
+        (false, 2) => LevelFilter::DEBUG,
+        (false, _) => LevelFilter::TRACE,
+    };
+
+    // RUST_LOG still takes precedence over the flags when it is set.
+    let mut filter = EnvFilter::builder()
+        .with_default_directive(level.into())
+        .from_env_lossy();
+    // The commands a dry run skips are its whole output, so they show by default.
+    if !cli.quiet && std::env::var_os("RUST_LOG").is_none() {
+        filter = filter.add_directive(
+            format!("{DRY_RUN_TARGET}=info")
+                .parse()
+                .expect("dry run directive should be valid"),
+        );
+    }
+
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr)
+        .init();
+}
+
+const CONFIG_FILE: &str = "config.json";
+
+fn config_path(explicit: Option<&str>) -> String {
+    if let Some(path) = explicit {
+        return path.to_string();
+    }
+
+    let parents = std::env::current_dir().into_iter().flat_map(|dir| {
+        dir.ancestors()
+            .map(|dir| dir.join(CONFIG_FILE))
+            .collect::<Vec<_>>()
+    });
+    let user = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .map(|dir| dir.join("docker-pullator").join(CONFIG_FILE));
+
+    let path = parents
+        .chain(user)
+        .find(|path| path.is_file())
+        .map(|path| path.to_string_lossy().into_owned())
+        // Without any config a new one is created in the current directory.
+        .unwrap_or_else(|| CONFIG_FILE.to_string());
+
+    tracing::info!("Using config {path}");
+    path
+}
+
+/// Takes an advisory lock on a `.lock` file next to the config, so two commands can't
+/// overwrite each other's changes.
+fn lock_config(path: &str) -> anyhow::Result<std::fs::File> {
+    let lock_path = format!("{path}.lock");
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&lock_path)
+        .with_context(|| format!("Failed to open lock file {lock_path}"))?;
+
+    match file.try_lock() {
+        Ok(()) => Ok(file),
+        Err(std::fs::TryLockError::WouldBlock) => anyhow::bail!(
+            "{path} is in use by another docker-pullator process, wait for it to finish or pass --force-lock"
+        ),
+        Err(std::fs::TryLockError::Error(error)) => {
+            Err(error).with_context(|| format!("Failed to lock {lock_path}"))
+        }
+    }
+}
+
+fn restore(cli: &Cli, config_path: &str, command: &RestoreCommand) -> anyhow::Result<()> {
+    let backups = config_backups(config_path)?;
+    let names = backups
+        .iter()
+        .map(|backup| {
+            backup
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default()
+        })
+        .collect::<Vec<_>>();
+
+    if command.list {
+        for (number, name) in names.iter().enumerate() {
+            println!("{:>3}  {name}", number + 1);
+        }
+        return Ok(());
+    }
+
+    if backups.is_empty() {
+        anyhow::bail!("{config_path} has no backups to restore");
+    }
+
+    let interactive = !cli.non_interactive && std::io::stdin().is_terminal();
+    let index = match &command.backup {
+        Some(backup) => match backup.parse::<usize>() {
+            Ok(number) if (1..=backups.len()).contains(&number) => number - 1,
+            Ok(number) => anyhow::bail!("There is no backup {number}, see restore --list"),
+            Err(_) => names
+                .iter()
+                .position(|name| name == backup)
+                .with_context(|| format!("There is no backup {backup}, see restore --list"))?,
+        },
+        None if interactive => {
+            let name = Select::new("Please choose a backup to restore:", names.clone())
+                .prompt()
+                .context("Failed to prompt")?;
+            names.iter().position(|x| *x == name).unwrap_or_default()
+        }
+        None => anyhow::bail!("Pass the backup to restore, see restore --list"),
+    };
+
+    let name = &names[index];
+    let backup = backups[index].to_string_lossy();
+    let config = read_config(&backup).with_context(|| format!("Failed to read backup {name}"))?;
+    config
+        .validate()
+        .with_context(|| format!("Backup {name} is invalid"))?;
+
+    if !command.yes {
+        if !interactive {
+            anyhow::bail!(
+                "Refusing to restore {name} without confirmation, pass --yes to restore it"
+            );
+        }
+
+        let confirmed = Confirm::new(&format!("Restore {config_path} from {name}?"))
+            .with_default(false)
+            .prompt()
+            .context("Failed to prompt")?;
+        if !confirmed {
+            return Ok(());
+        }
+    }
+
+    if cli.no_write {
+        tracing::info!("config changes not persisted (--no-write)");
+        return Ok(());
+    }
+
+    // The current config is backed up as well, so a restore can be undone.
+    let output_path = cli.output.as_deref().unwrap_or(config_path);
+    write_config(output_path, &config, cli.backups)?;
+    eprintln!("Restored {output_path} from {name}");
+    Ok(())
+}
+
+fn load_policy(cli: &Cli, config: &Config) -> anyhow::Result<Policy> {
+    match &cli.policy {
+        Some(path) => read_policy(path),
+        None => Ok(config.policy.clone().unwrap_or_default()),
+    }
+}
+
+fn hub_url(cli: &Cli) -> String {
+    cli.hub_url
+        .clone()
+        .or_else(|| std::env::var("HUB_URL").ok().filter(|url| !url.is_empty()))
+        .unwrap_or_else(|| DEFAULT_HUB_URL.to_string())
+}
+
+fn completions(command: &CompletionsCommand) {
+    clap_complete::generate(
+        command.shell,
+        &mut Cli::command(),
+        env!("CARGO_PKG_NAME"),
+        &mut std::io::stdout(),
+    );
+}
+
+async fn list(config: &Config, command: &ListCommand, color: bool) -> anyhow::Result<()> {
+    let profiles = config
+        .pull_profiles
+        .values()
+        .map(|profile| ListItem {
+            image: profile.image(),
+            tags: profile.tags.clone(),
+            pattern: profile.pattern.clone(),
+            notes: profile.notes.clone(),
+        })
+        .collect::<Vec<_>>();
+
+    if let ListFormat::Json = command.format {
+        let content =
+            serde_json::to_string_pretty(&profiles).context("Failed to serialize profiles")?;
+        println!("{content}");
+        return Ok(());
+    }
+
+    if profiles.is_empty() {
+        println!("no profiles configured");
+        return Ok(());
+    }
+
+    match command.format {
+        ListFormat::Plain => {
+            for (index, profile) in profiles.iter().enumerate() {
+                if index > 0 {
+                    println!();
+                }
+
+                println!("{}", profile.image);
+                for tag in &profile.tags {
+                    match profile.notes.get(tag) {
+                        Some(note) => println!("  {tag}  # {note}"),
+                        None => println!("  {tag}"),
+                    }
+                }
+            }
+        }
+        ListFormat::Table => {
+            let width = profiles
+                .iter()
+                .map(|profile| profile.image.len())
+                .chain(std::iter::once("IMAGE".len()))
+                .max()
+                .unwrap_or_default();
+
+            println!(
+                "{}",
+                paint(&format!("{:width$}  TAGS", "IMAGE"), "1", color)
+            );
+            for profile in &profiles {
+                let image = paint(&format!("{:width$}", profile.image), "36", color);
+                println!("{image}  {}", profile.tags.join(", "));
+            }
+        }
+        ListFormat::Json => unreachable!("json output is handled above"),
+    }
+
+    Ok(())
+}
+
+async fn doctor(cli: &Cli) -> anyhow::Result<()> {
+    let mut failed = 0;
+    let mut check = |name: &str, result: anyhow::Result<String>| match result {
+        Ok(detail) => println!("  ok    {name}: {detail}"),
+        Err(error) => {
+            failed += 1;
+            println!("  FAIL  {name}: {error:#}");
+        }
+    };
+
+    let config_path = config_path(cli.config.as_deref());
+    let config = read_config(&config_path).and_then(|config| {
+        config.validate()?;
+        Ok(config)
+    });
+    let docker_bin = cli
+        .docker_bin
+        .clone()
+        .or_else(|| {
+            config
+                .as_ref()
+                .ok()
+                .and_then(|config| config.docker_bin.clone())
+        })
+        .unwrap_or_else(|| "docker".to_string());
+    check(
+        "config",
+        config
+            .as_ref()
+            .map(|config| format!("{config_path}, {} tag(s)", config.tag_count()))
+            .map_err(|error| anyhow::anyhow!("{error:#}")),
+    );
+
+    let docker = Docker::new(docker_bin.clone(), DockerOutput::Capture);
+    let version = docker
+        .command()
+        .query()
+        .arg("--version")
+        .output()
+        .await
+        .with_context(|| format!("{docker_bin} was not found, install it or set --docker-bin"))
+        .map(|output| {
+            let version = String::from_utf8_lossy(&output.stdout).trim().to_string();
+            if version.is_empty() {
+                format!("{docker_bin} found")
+            } else {
+                version
+            }
+        });
+    let found = version.is_ok();
+    check("docker binary", version);
+
+    let daemon = if found {
+        docker
+            .command()
+            .query()
+            .arg("info")
+            .output()
+            .await
+            .context("Failed to run docker info")
+            .and_then(|output| {
+                if output.status.success() {
+                    Ok("reachable".to_string())
+                } else {
+                    Err(docker_error("docker info", &output))
+                }
+            })
+    } else {
+        Err(anyhow::anyhow!("skipped, no docker binary"))
+    };
+    check("docker daemon", daemon);
+
+    // A single small page is enough to prove Docker Hub answers, so skip the cache.
+    let hub = Hub::new(&hub_url(cli), cli.http_timeout)?;
+    let options = FetchOptions {
+        max_pages: Some(1),
+        page_size: Some(1),
+        ..Default::default()
+    };
+    let hub_result = fetch_tags(&hub, None, Some("library"), "hello-world", options, None)
+        .await
+        .map(|_| "reachable".to_string())
+        .map_err(Into::into);
+    check("Docker Hub", hub_result);
+
+    if failed > 0 {
+        anyhow::bail!("{failed} check(s) failed");
+    }
+
+    Ok(())
+}
+
+async fn status(config: &Config, hub: &Hub, docker: &Docker, color: bool) -> anyhow::Result<()> {
+    let info = docker
+        .command()
+        .query()
+        .arg("info")
+        .output()
+        .await
+        .context("Failed to run docker info")?;
+
+    if !info.status.success() {
+        anyhow::bail!(
+            "Docker daemon is not reachable: {}",
+            String::from_utf8_lossy(&info.stderr).trim()
+        );
+    }
+
+    let config = &resolve_latest(config, hub).await?;
+    let token = hub_token(hub, config.auth.as_ref()).await?;
+    let mut rows = vec![];
+
+    for profile in config.pull_profiles.values() {
+        let image = profile.image();
+        let response = fetch_tags(
+            hub,
+            profile.registry.as_deref(),
+            profile.library.as_ref(),
+            &profile.repo,
+            FetchOptions::default(),
+            token.as_deref(),
+        )
+        .await?;
+
+        for tag in profile.active_tags() {
+            let expected = profile.pinned_digest(tag).cloned().or_else(|| {
+                response
+                    .results
+                    .iter()
+                    .find(|item| &item.name == tag)
+                    .and_then(|item| item.digest.clone())
+            });
+
+            let reference = profile.reference(tag);
+            let state = match local_digests(docker, &reference).await? {
+                None => ImageState::Missing,
+                Some(local) if expected.is_some_and(|digest| !has_digest(&local, &digest)) => {
+                    ImageState::Stale
+                }
+                Some(_) => ImageState::Present,
+            };
+
+            rows.push((image.clone(), tag.clone(), state));
+        }
+    }
+
+    let image_width = rows
+        .iter()
+        .map(|(image, _, _)| image.len())
+        .chain(std::iter::once("IMAGE".len()))
+        .max()
+        .unwrap_or_default();
+    let tag_width = rows
+        .iter()
+        .map(|(_, tag, _)| tag.len())
+        .chain(std::iter::once("TAG".len()))
+        .max()
+        .unwrap_or_default();
+
+    let header = format!("{:image_width$}  {:tag_width$}  STATE", "IMAGE", "TAG");
+    println!("{}", paint(&header, "1", color));
+    for (image, tag, state) in &rows {
+        let state = paint(&state.to_string(), state.style(), color);
+        println!("{image:image_width$}  {tag:tag_width$}  {state}");
+    }
+
+    Ok(())
+}
+
+async fn arch_report(
+    config: &Config,
+    hub: &Hub,
+    command: &ArchReportCommand,
+) -> anyhow::Result<()> {
+    let config = command.selection.apply(config)?;
+    let token = hub_token(hub, config.auth.as_ref()).await?;
+    let mut incomplete = 0;
+
+    for profile in config.pull_profiles.values() {
+        let response = fetch_tags(
+            hub,
+            profile.registry.as_deref(),
+            profile.library.as_ref(),
+            &profile.repo,
+            FetchOptions::default(),
+            token.as_deref(),
+        )
+        .await?;
+
+        let expected = if profile.platforms.is_empty() {
+            &command.platform
+        } else {
+            &profile.platforms
+        };
+
+        let mut rows = vec![];
+        for tag in profile.active_tags() {
+            let Some(item) = response.results.iter().find(|item| &item.name == tag) else {
+                rows.push((
+                    tag.clone(),
+                    "-".to_string(),
+                    "not found upstream".to_string(),
+                ));
+                continue;
+            };
+
+            let mut platforms = item
+                .images
+                .iter()
+                .filter_map(|image| image.platform())
+                .collect::<Vec<_>>();
+            platforms.sort();
+            platforms.dedup();
+            let platforms = if platforms.is_empty() {
+                "unknown".to_string()
+            } else {
+                platforms.join(", ")
+            };
+
+            let missing = expected
+                .iter()
+                .filter(|platform| !item.matches_platform_exactly(platform))
+                .cloned()
+                .collect::<Vec<_>>();
+            let missing = if missing.is_empty() {
+                "-".to_string()
+            } else {
+                incomplete += 1;
+                missing.join(", ")
+            };
+
+            rows.push((tag.clone(), platforms, missing));
+        }
+
+        let tag_width = rows
+            .iter()
+            .map(|(tag, _, _)| tag.len())
+            .chain(std::iter::once("TAG".len()))
+            .max()
+            .unwrap_or_default();
+        let platforms_width = rows
+            .iter()
+            .map(|(_, platforms, _)| platforms.len())
+            .chain(std::iter::once("PLATFORMS".len()))
+            .max()
+            .unwrap_or_default();
+
+        println!("{}", profile.image());
+        println!(
+            "  {:tag_width$}  {:platforms_width$}  MISSING",
+            "TAG", "PLATFORMS"
+        );
+        for (tag, platforms, missing) in &rows {
+            println!("  {tag:tag_width$}  {platforms:platforms_width$}  {missing}");
+        }
+    }
+
+    if incomplete > 0 {
+        if command.strict {
+            anyhow::bail!("{incomplete} tag(s) are missing expected platforms");
+        }
+        tracing::warn!("{incomplete} tag(s) are missing expected platforms");
+    }
+
+    Ok(())
+}
+
+async fn du(
+    config: &Config,
+    hub: &Hub,
+    docker: &Docker,
+    command: &DuCommand,
+) -> anyhow::Result<()> {
+    let config = command.selection.apply(config)?;
+    let token = hub_token(hub, config.auth.as_ref()).await?;
+    let host = command.platform.clone().unwrap_or_else(|| {
+        let arch = match std::env::consts::ARCH {
+            "x86_64" => "amd64",
+            "aarch64" => "arm64",
+            arch => arch,
+        };
+        format!("linux/{arch}")
+    });
+    let (mut local_total, mut download_total) = (0, 0);
+
+    for profile in config.pull_profiles.values() {
+        let response = fetch_tags(
+            hub,
+            profile.registry.as_deref(),
+            profile.library.as_ref(),
+            &profile.repo,
+            FetchOptions::default(),
+            token.as_deref(),
+        )
+        .await?;
+
+        let platforms = if profile.platforms.is_empty() {
+            std::slice::from_ref(&host)
+        } else {
+            &profile.platforms[..]
+        };
+
+        let mut rows = vec![];
+        let (mut local, mut download) = (0, 0);
+        for tag in profile.active_tags() {
+            if let Some(size) = local_size(docker, &profile.reference(tag)).await? {
+                local += size;
+                rows.push((tag.clone(), format_size(size), "local"));
+                continue;
+            }
+
+            let item = response.results.iter().find(|item| &item.name == tag);
+            let sizes = platforms
+                .iter()
+                .map(|platform| item.and_then(|item| item.size(platform)))
+                .collect::<Option<Vec<_>>>();
+            match sizes {
+                Some(sizes) => {
+                    let size = sizes.into_iter().sum();
+                    download += size;
+                    rows.push((tag.clone(), format_size(size), "download, compressed"));
+                }
+                None => rows.push((tag.clone(), "?".to_string(), "no upstream size")),
+            }
+        }
+
+        let tag_width = rows
+            .iter()
+            .map(|(tag, _, _)| tag.len())
+            .chain(std::iter::once("TAG".len()))
+            .max()
+            .unwrap_or_default();
+
+        println!("{}", profile.image());
+        println!("  {:tag_width$}  {:>9}  SOURCE", "TAG", "SIZE");
+        for (tag, size, source) in &rows {
+            println!("  {tag:tag_width$}  {size:>9}  {source}");
+        }
+        println!(
+            "  {} local, {} to download",
+            format_size(local),
+            format_size(download)
+        );
+
+        local_total += local;
+        download_total += download;
+    }
+
+    // Tags of an image share layers, so the sums are an upper bound.
+    println!(
+        "Total: {} local, {} to download",
+        format_size(local_total),
+        format_size(download_total)
+    );
+
+    Ok(())
+}
+
+/// Formats bytes in decimal units like docker, e.g. `117.3MB`.
+fn format_size(bytes: u64) -> String {
+    let units = ["B", "kB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1000.0 && unit < units.len() - 1 {
+        size /= 1000.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{bytes}B")
+    } else {
+        format!("{size:.1}{}", units[unit])
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ImageState {
+    Present,
+    Missing,
+    Stale,
+}
+
+impl ImageState {
+    /// The ANSI color of the state: green, red or yellow.
+    fn style(self) -> &'static str {
+        match self {
+            Self::Present => "32",
+            Self::Missing => "31",
+            Self::Stale => "33",
+        }
+    }
+}
+
+impl std::fmt::Display for ImageState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let state = match self {
+            Self::Present => "present",
+            Self::Missing => "missing",
+            Self::Stale => "stale",
+        };
+
+        f.write_str(state)
+    }
+}
+
+async fn plan(config: &Config, hub: &Hub, docker: &Docker, json: bool) -> anyhow::Result<()> {
+    let changes = docker_pullator::plan(config, hub, docker).await?;
+
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&changes).context("Failed to serialize plan")?
+        );
+        return Ok(());
+    }
+
+    if changes.is_empty() {
+        println!("nothing to do");
+        return Ok(());
+    }
+
+    for entry in &changes {
+        let (sign, reason) = match entry.change {
+            PlanChange::Pull => ("+", "not present locally"),
+            PlanChange::Update => ("~", "digest changed upstream"),
+            PlanChange::Unconfigured => ("-", "no longer in the config"),
+        };
+        println!("{sign} {}:{}  {reason}", entry.image, entry.tag);
+    }
+
+    Ok(())
+}
+
+async fn pull(
+    config: &Config,
+    hub: &Hub,
+    docker: &Docker,
+    command: &PullCommand,
+    report: &mut Report,
+) -> Result<(), PullatorError> {
+    let mut config = command
+        .selection
+        .apply(config)
+        .map_err(PullatorError::Config)?;
+    // --tags may name tags that only a pattern or an @latest directive selects. The resolved
+    // patterns are dropped so the pull doesn't add back the tags --tags left out.
+    if command.scope.tags.is_some() {
+        config = resolve_latest(&config, hub).await?;
+        for profile in config.pull_profiles.values_mut() {
+            profile.pattern = None;
+        }
+    }
+    let config = command
+        .scope
+        .apply(&config)
+        .map_err(PullatorError::Config)?;
+
+    pull_images(&config, hub, docker, &command.options, report).await
+}
+
+async fn push(
+    config: &Config,
+    hub: &Hub,
+    docker: &Docker,
+    command: &PushCommand,
+    interactive: bool,
+    report: &mut Report,
+) -> Result<(), PullatorError> {
+    let config = &command
+        .selection
+        .apply(config)
+        .map_err(PullatorError::Config)?;
+    // The confirmation counts the tags the @latest directives stand for.
+    let config = &resolve_latest(config, hub).await?;
+    let clean = command.clean
+        && (command.dry_run
+            || confirm_removal(config.tag_count(), command.yes, interactive)
+                .map_err(PullatorError::Config)?);
+
+    push_images(
+        config,
+        hub,
+        docker,
+        &PushOptions {
+            registries: command.registry.clone(),
+            concurrency: command.concurrency,
+            login: command.login,
+            platform: command.platform.clone(),
+            clean,
+            keep_local: command.keep_local,
+            multi_arch: command.multi_arch,
+            rename: command.rename.iter().cloned().collect(),
+        },
+        report,
+    )
+    .await
+}
+
+async fn mirror(
+    config: &Config,
+    hub: &Hub,
+    docker: &Docker,
+    command: &MirrorCommand,
+    report: &mut Report,
+) -> anyhow::Result<()> {
+    let config = &command.selection.apply(config)?;
+    let skopeo = docker.with_bin(command.skopeo_bin.clone());
+
+    let found = skopeo.command().query().arg("--version").output().await;
+    if let Err(error) = found {
+        tracing::warn!(
+            "Failed to run {}, pulling and pushing through docker instead: {error}",
+            command.skopeo_bin
+        );
+        // Only the fallback needs docker, mirror itself never touches the daemon.
+        docker.check_installed().await?;
+
+        let pull = PullOptions {
+            concurrency: command.concurrency,
+            platform: command.platform.clone(),
+            ..Default::default()
+        };
+        pull_images(config, hub, docker, &pull, report).await?;
+
+        let push = PushOptions {
+            registries: command.registry.clone(),
+            concurrency: command.concurrency,
+            platform: command.platform.clone(),
+            ..Default::default()
+        };
+        return Ok(push_images(config, hub, docker, &push, report).await?);
+    }
+
+    let options = MirrorOptions {
+        registries: command.registry.clone(),
+        concurrency: command.concurrency,
+        platform: command.platform.clone(),
+    };
+    Ok(mirror_images(config, hub, &skopeo, &options, report).await?)
+}
+
+/// Prints the report when requested, even if the command failed halfway.
+fn finish_report(
+    report: &Report,
+    json: bool,
+    metrics_file: Option<&str>,
+    result: anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    if json {
+        println!("{}", report.to_json()?);
+    }
+
+    if let Some(path) = metrics_file {
+        report.write_metrics(path, result.is_ok())?;
+    }
+
+    result
+}
+
+/// Pulls like [`docker_pullator::pull`] and prints the summary of the pulled tags.
+async fn pull_images(
+    config: &Config,
+    hub: &Hub,
+    docker: &Docker,
+    options: &PullOptions,
+    report: &mut Report,
+) -> Result<(), PullatorError> {
+    let start = report.entries().len();
+    let result = docker_pullator::pull(config, hub, docker, options, report).await;
+
+    // Runs that failed before pulling anything have nothing to sum up.
+    let entries = &report.entries()[start..];
+    if result.is_ok() || !entries.is_empty() {
+        let count = |status| {
+            entries
+                .iter()
+                .filter(|entry| entry.status == status)
+                .count()
+        };
+        eprintln!(
+            "Pull summary: {} pulled, {} skipped, {} failed",
+            count(ReportStatus::Pulled),
+            count(ReportStatus::Skipped),
+            count(ReportStatus::Failed)
+        );
+
+        for entry in entries {
+            if let Some(error) = &entry.error {
+                let separator = if is_digest(&entry.tag) { '@' } else { ':' };
+                let platform = entry
+                    .platform
+                    .as_ref()
+                    .map(|platform| format!(" ({platform})"))
+                    .unwrap_or_default();
+                eprintln!(
+                    "  failed {}{separator}{}{platform}: {error}",
+                    entry.image, entry.tag
+                );
+            }
+        }
+    }
+
+    result
+}
+
+/// Pushes like [`docker_pullator::push`] and prints the summary of every registry.
+async fn push_images(
+    config: &Config,
+    hub: &Hub,
+    docker: &Docker,
+    options: &PushOptions,
+    report: &mut Report,
+) -> Result<(), PullatorError> {
+    let start = report.entries().len();
+    let result = docker_pullator::push(config, hub, docker, options, report).await;
+    print_push_summary(
+        "Push",
+        &options.registries,
+        &report.entries()[start..],
+        &result,
+    );
+    result
+}
+
+/// Mirrors like [`docker_pullator::mirror`] and prints the summary of every registry.
+async fn mirror_images(
+    config: &Config,
+    hub: &Hub,
+    skopeo: &Docker,
+    options: &MirrorOptions,
+    report: &mut Report,
+) -> Result<(), PullatorError> {
+    let start = report.entries().len();
+    let result = docker_pullator::mirror(config, hub, skopeo, options, report).await;
+    print_push_summary(
+        "Mirror",
+        &options.registries,
+        &report.entries()[start..],
+        &result,
+    );
+    result
+}
+
+fn print_push_summary(
+    name: &str,
+    registries: &[String],
+    entries: &[ReportEntry],
+    result: &Result<(), PullatorError>,
+) {
+    // Runs that failed before pushing anything, e.g. at the login, have nothing to sum up.
+    if result.is_err() && entries.is_empty() {
+        return;
+    }
+
+    eprintln!("{name} summary:");
+    for registry in registries {
+        let entries = entries
+            .iter()
+            .filter(|entry| entry.registry.as_ref() == Some(registry))
+            .collect::<Vec<_>>();
+
+        // Registries that failed as a whole have entries without a target.
+        if let Some(entry) = entries.iter().find(|entry| entry.target.is_none()) {
+            let error = entry.error.as_deref().unwrap_or_default();
+            eprintln!("  {registry}: failed: {error}");
+            continue;
+        }
+
+        let failed = entries
+            .iter()
+            .filter(|entry| entry.status == ReportStatus::Failed)
+            .collect::<Vec<_>>();
+        if failed.is_empty() {
+            eprintln!("  {registry}: ok");
+            continue;
+        }
+
+        eprintln!("  {registry}: failed {} image(s)", failed.len());
+        for entry in failed {
+            eprintln!(
+                "    failed {}: {}",
+                entry.target.as_deref().unwrap_or_default(),
+                entry.error.as_deref().unwrap_or_default()
+            );
+        }
+    }
+}
+
+async fn rate_limit(config: &Config, hub: &Hub) -> anyhow::Result<()> {
+    match docker_pullator::rate_limit(hub, config.auth.as_ref()).await? {
+        (account, Some(RateLimit { limit, remaining })) => {
+            println!("{remaining} of {limit} pulls remaining ({account})")
+        }
+        (account, None) => println!("No pull rate limit applies ({account})"),
+    }
+
+    Ok(())
+}
+
+async fn sync(
+    config: &Config,
+    hub: &Hub,
+    docker: &Docker,
+    command: &SyncCommand,
+    interactive: bool,
+    report: &mut Report,
+) -> anyhow::Result<()> {
+    let config = &command.selection.apply(config)?;
+
+    // Ask before pulling so an unattended run doesn't stop after the pull.
+    let clean_after = command.clean
+        && (command.dry_run || confirm_removal(config.tag_count(), command.yes, interactive)?);
+
+    if command.login {
+        registry_login(config, docker, &command.registry).await?;
+    }
+
+    let pulled = pull_images(config, hub, docker, &command.pull, report)
+        .await
+        .map_err(anyhow::Error::from);
+
+    let pulled = match pulled {
+        Err(error) if !command.continue_on_error => {
+            eprintln!("Sync summary:");
+            eprintln!("  pull: failed");
+            eprintln!("  push: skipped, pass --continue-on-error to push anyway");
+
+            return Err(ExitError::new(3, error).into());
+        }
+        pulled => pulled,
+    };
+
+    // Pushing the images that failed to pull would only fail again and hide the cause.
+    let config = &if pulled.is_err() {
+        report.pulled_config(config)
+    } else {
+        config.clone()
+    };
+
+    let pushed = push_images(
+        config,
+        hub,
+        docker,
+        &PushOptions {
+            registries: command.registry.clone(),
+            concurrency: command.push_concurrency,
+            login: false,
+            platform: None,
+            clean: clean_after,
+            keep_local: false,
+            multi_arch: command.multi_arch,
+            rename: BTreeMap::new(),
+        },
+        report,
+    )
+    .await
+    .map_err(anyhow::Error::from);
+
+    let phase = |result: &anyhow::Result<()>| if result.is_ok() { "ok" } else { "failed" };
+    eprintln!("Sync summary:");
+    eprintln!("  pull: {}", phase(&pulled));
+    eprintln!("  push: {}", phase(&pushed));
+
+    match (pulled, pushed) {
+        (Ok(()), Ok(())) => Ok(()),
+        (Err(error), Ok(())) => Err(ExitError::new(3, error).into()),
+        (Ok(()), Err(error)) => Err(ExitError::new(4, error).into()),
+        (Err(pull), Err(push)) => {
+            Err(ExitError::new(5, anyhow::anyhow!("{pull:#}; {push:#}")).into())
+        }
+    }
+}
+
+/// An error that exits the process with a specific code instead of 1.
+#[derive(Debug)]
+struct ExitError {
+    code: u8,
+    error: anyhow::Error,
+}
+
+impl ExitError {
+    fn new(code: u8, error: anyhow::Error) -> Self {
+        Self { code, error }
+    }
+}
+
+impl std::fmt::Display for ExitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:#}", self.error)
+    }
+}
+
+impl std::error::Error for ExitError {}
+
+async fn watch(
+    cli: &Cli,
+    config_path: &str,
+    hub: &Hub,
+    docker: &Docker,
+    command: &SyncCommand,
+    interval: Duration,
+) -> anyhow::Result<()> {
+    let interrupted = Arc::new((AtomicBool::new(false), tokio::sync::Notify::new()));
+    tokio::spawn({
+        let interrupted = interrupted.clone();
+        async move {
+            if tokio::signal::ctrl_c().await.is_ok() {
+                eprintln!("Interrupted, stopping after the current sync");
+                interrupted.0.store(true, Ordering::SeqCst);
+                interrupted.1.notify_one();
+            }
+        }
+    });
+
+    for cycle in 1.. {
+        let mut report = Report::default();
+        // Unattended cycles never prompt, so --clean needs --yes.
+        let result = match read_config(config_path).and_then(|config| {
+            config.validate()?;
+            load_policy(cli, &config)?.check_config(&config)?;
+            Ok(config)
+        }) {
+            Ok(config) => {
+                let hub = hub.clone().with_credentials(config.registries.clone());
+                sync(&config, &hub, docker, command, false, &mut report).await
+            }
+            Err(error) => Err(error),
+        };
+
+        match finish_report(&report, cli.json, cli.metrics_file.as_deref(), result) {
+            Ok(()) => eprintln!("Sync {cycle} finished"),
+            Err(error) => eprintln!("Sync {cycle} failed: {error:#}"),
+        }
+
+        if interrupted.0.load(Ordering::SeqCst) {
+            break;
+        }
+
+        eprintln!("Next sync in {}", humantime::format_duration(interval));
+
+        tokio::select! {
+            _ = tokio::time::sleep(interval) => {}
+            _ = interrupted.1.notified() => break,
+        }
+    }
+
+    Ok(())
+}
+
+async fn export(
+    config: &Config,
+    hub: &Hub,
+    docker: &Docker,
+    command: &ExportCommand,
+) -> anyhow::Result<()> {
+    let config = &resolve_latest(config, hub).await?;
+    let references = config
+        .pull_profiles
+        .values()
+        .flat_map(|profile| profile.active_tags().map(|tag| profile.reference(tag)))
+        .collect::<Vec<_>>();
+
+    if references.is_empty() {
+        anyhow::bail!("No images configured to export");
+    }
+
+    if command.pull_missing {
+        let mut options = command.pull.clone();
+        options.if_missing = true;
+
+        pull_images(config, hub, docker, &options, &mut Report::default()).await?;
+    } else if !docker.is_dry_run() {
+        let mut missing = vec![];
+        for reference in &references {
+            if local_digests(docker, reference).await?.is_none() {
+                missing.push(reference.as_str());
+            }
+        }
+
+        if !missing.is_empty() {
+            anyhow::bail!(
+                "Images are not present locally, pull them first or pass --pull-missing: {}",
+                missing.join(", ")
+            );
+        }
+    }
+
+    let mut save = docker.command();
+    save.arg("save").arg("--output").arg(&command.output);
+    for reference in &references {
+        save.arg(reference);
+    }
+
+    let output = save.output().await.context("Failed to save images")?;
+    if !output.status.success() {
+        return Err(docker_error(
+            &format!("Saving images to {}", command.output),
+            &output,
+        ));
+    }
+
+    Ok(())
+}
+
+async fn import(docker: &Docker, command: &ImportCommand) -> anyhow::Result<()> {
+    let output = docker
+        .command()
+        .arg("load")
+        .arg("--input")
+        .arg(&command.input)
+        .output()
+        .await
+        .context("Failed to load images")?;
+
+    if !output.status.success() {
+        return Err(docker_error(
+            &format!("Loading images from {}", command.input),
+            &output,
+        ));
+    }
+
+    Ok(())
+}
+
+async fn edit(config: &mut Config, hub: &Hub, interactive: bool) -> anyhow::Result<()> {
+    if !interactive {
+        anyhow::bail!(
+            "edit needs an interactive terminal, use remove to edit the config from scripts"
+        );
+    }
+
+    let profiles = config.pull_profiles.keys().cloned().collect::<Vec<_>>();
+
+    let image = Select::new("Please choose profile to edit:", profiles)
+        .prompt()
+        .context("Failed to prompt")?;
+
+    let mut profile = config
+        .pull_profiles
+        .get(&image)
+        .expect("profile should exist")
+        .clone();
+
+    // The update dates are only a hint, so editing works without Docker Hub.
+    let token = hub_token(hub, config.auth.as_ref())
+        .await
+        .unwrap_or_default();
+    let response = match fetch_tags(
+        hub,
+        profile.registry.as_deref(),
+        profile.library.as_ref(),
+        &profile.repo,
+        FetchOptions::default(),
+        token.as_deref(),
+    )
+    .await
+    {
+        Ok(response) => response,
+        Err(error) => {
+            tracing::warn!("Failed to fetch tags for {image}: {error:#}");
+            FetchTagsResponse {
+                results: vec![],
+                next: None,
+            }
+        }
+    };
+
+    let choices = profile
+        .tags
+        .iter()
+        .map(|tag| {
+            let mut choice = TagChoice::new(tag.clone(), &response);
+            choice.note = profile.notes.get(tag).cloned();
+            choice
+        })
+        .collect();
+
+    let tags = MultiSelect::new("Please choose tags to keep:", choices)
+        .with_all_selected_by_default()
+        .prompt()
+        .context("Failed to prompt")?;
+
+    profile.tags = tags.into_iter().map(|choice| choice.name).collect();
+    profile.digests.retain(|tag, _| profile.tags.contains(tag));
+    profile.notes.retain(|tag, _| profile.tags.contains(tag));
+
+    if profile.tags.is_empty() && profile.pattern.is_none() {
+        config.pull_profiles.remove(&image);
+    } else {
+        config.pull_profiles.insert(image, profile);
+    }
+
+    Ok(())
+}
+
+async fn refresh(config: &mut Config, hub: &Hub, command: &RefreshCommand) -> anyhow::Result<()> {
+    let token = hub_token(hub, config.auth.as_ref()).await?;
+    let mut failed = 0;
+    let mut missing_count = 0;
+
+    for (image, profile) in config.pull_profiles.iter_mut() {
+        let response = match fetch_tags(
+            hub,
+            profile.registry.as_deref(),
+            profile.library.as_ref(),
+            &profile.repo,
+            FetchOptions::default(),
+            token.as_deref(),
+        )
+        .await
+        {
+            Ok(response) => response,
+            Err(error) => {
+                eprintln!("{image}: failed: {error:#}");
+                failed += 1;
+                continue;
+            }
+        };
+
+        // @latest directives and digests added with --as-digest never name an
+        // upstream tag themselves.
+        let missing = profile
+            .tags
+            .iter()
+            .filter(|tag| LatestDirective::parse(tag).is_none() && !is_digest(tag))
+            .filter(|tag| !response.results.iter().any(|item| &item.name == *tag))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        // Tags the pattern and the @latest directives select now but aren't configured.
+        let upstream = response
+            .results
+            .iter()
+            .map(|item| item.name.clone())
+            .collect::<Vec<_>>();
+        let added = match profile.resolve_tags(&upstream, true) {
+            Ok(tags) => tags
+                .into_iter()
+                .filter(|tag| !profile.tags.contains(tag))
+                .collect::<Vec<_>>(),
+            Err(error) => {
+                eprintln!("{image}: failed: {error:#}");
+                failed += 1;
+                continue;
+            }
+        };
 
-#[derive(Parser)]
-struct Cli {
-    /// The path to the config file
-    #[clap(long, default_value = "config.json")]
-    config: String,
+        if missing.is_empty() && added.is_empty() {
+            println!("{image}: up to date");
+            continue;
+        }
 
-    #[clap(subcommand)]
-    subcommand: SubCommand,
+        println!("{image}:");
+        for tag in &added {
+            println!("  + {tag} (selected by the pattern or @latest)");
+        }
+        for tag in &missing {
+            println!("  - {tag} (no longer upstream)");
+        }
+        missing_count += missing.len();
+
+        if command.remove_missing {
+            profile.tags.retain(|tag| !missing.contains(tag));
+            profile.digests.retain(|tag, _| !missing.contains(tag));
+            profile.notes.retain(|tag, _| !missing.contains(tag));
+        }
+    }
+
+    if command.remove_missing {
+        // Profiles without tags or a pattern left would fail validation.
+        config
+            .pull_profiles
+            .retain(|_, profile| !profile.tags.is_empty() || profile.pattern.is_some());
+    } else if missing_count > 0 {
+        eprintln!("Run refresh --remove-missing to drop {missing_count} missing tag(s)");
+    }
+
+    if failed > 0 {
+        anyhow::bail!("Failed to refresh {failed} image(s)");
+    }
+
+    Ok(())
 }
 
-#[derive(Parser)]
-enum SubCommand {
-    /// Add new images and tags to the config
-    Add(AddCommand),
-    /// Pull images from the config
-    Pull,
-    /// Clean images listed in the config
-    Clean,
-    /// Edit images and tags in the config
-    Edit,
-    /// Push images to a registry
-    Push(PushCommand),
-    /// Pull and push
-    Sync(SyncCommand),
+/// Where and how add fetches further pages of tags while browsing.
+struct TagPages<'a> {
+    source: (Option<&'a str>, Option<&'a String>, &'a str),
+    fetch: FetchOptions,
+    token: Option<&'a str>,
+    size: usize,
+    sort: TagSort,
 }
 
-#[derive(Parser)]
-struct AddCommand {
-    /// The library to pull the images from
-    #[clap(short, long)]
-    library: Option<String>,
+/// The choice at the end of a page that asks for the next one, tags can't contain spaces.
+const LOAD_MORE: &str = "[load more tags]";
+
+/// Offers the tags a page at a time, fetching further tag pages only once they are shown,
+/// and returns the tags selected across all pages.
+async fn browse_tags(
+    hub: &Hub,
+    pages: &TagPages<'_>,
+    response: &mut FetchTagsResponse,
+    mut offered: Vec<String>,
+    offer: &dyn Fn(&FetchTagsItem) -> bool,
+) -> anyhow::Result<Vec<String>> {
+    let (registry, library, repo) = pages.source;
+    let mut selected = vec![];
+    let mut start = 0;
+
+    loop {
+        while offered.len() < start + pages.size {
+            let Some(next) = response.next.take() else {
+                break;
+            };
 
-    /// The name of the image to pull
-    #[clap(short, long)]
-    repo: Option<String>,
+            let mut more = fetch_more_tags(
+                hub,
+                registry,
+                library,
+                repo,
+                &next,
+                pages.fetch,
+                pages.token,
+            )
+            .await?;
+            pages.sort.sort(&mut more.results);
+            offered.extend(
+                more.results
+                    .iter()
+                    .filter(|item| offer(item))
+                    .map(|item| item.name.clone()),
+            );
+            response.results.extend(more.results);
+            response.next = more.next;
+        }
 
-    /// The tags to pull
-    #[clap(short, long)]
-    tags: Option<Vec<String>>,
+        let end = offered.len().min(start + pages.size);
+        let mut choices = offered[start..end]
+            .iter()
+            .map(|tag| TagChoice::new(tag.clone(), response))
+            .collect::<Vec<_>>();
+        if end < offered.len() || response.next.is_some() {
+            choices.push(TagChoice {
+                name: LOAD_MORE.to_string(),
+                updated: None,
+                note: None,
+            });
+        }
+        if choices.is_empty() {
+            break;
+        }
+
+        let page = start / pages.size + 1;
+        let chosen = MultiSelect::new(
+            &format!("Please choose wanted images, page {page}:"),
+            choices,
+        )
+        .with_help_message("select the last choice to see more tags")
+        .prompt()
+        .context("Failed to prompt")?;
+
+        let load_more = chosen.iter().any(|choice| choice.name == LOAD_MORE);
+        selected.extend(
+            chosen
+                .into_iter()
+                .filter(|choice| choice.name != LOAD_MORE)
+                .map(|choice| choice.name),
+        );
+        if !load_more {
+            break;
+        }
+
+        start = end;
+    }
+
+    Ok(selected)
 }
 
-#[derive(Parser)]
-struct PushCommand {
-    /// The registry to push the images to
-    #[clap(short, long)]
-    registry: String,
+/// A tag offered in a prompt, labelled with its last update on the registry when known.
+struct TagChoice {
+    name: String,
+    updated: Option<String>,
+    note: Option<String>,
+}
 
-    /// Clean after push
-    #[clap(short, long)]
-    clean: bool,
+impl TagChoice {
+    fn new(name: String, response: &FetchTagsResponse) -> Self {
+        let updated = response
+            .results
+            .iter()
+            .find(|item| item.name == name)
+            .and_then(|item| item.last_updated.as_deref())
+            .and_then(|updated| updated.get(..10))
+            .map(str::to_string);
+
+        Self {
+            name,
+            updated,
+            note: None,
+        }
+    }
 }
 
-#[derive(Parser)]
-struct SyncCommand {
-    /// The registry to push the images to
-    #[clap(short, long)]
-    registry: String,
+impl std::fmt::Display for TagChoice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.updated {
+            Some(updated) => write!(f, "{} (updated {updated})", self.name)?,
+            None => f.write_str(&self.name)?,
+        }
 
-    /// Clean after push
-    #[clap(short, long)]
-    clean: bool,
+        match &self.note {
+            Some(note) => write!(f, " # {note}"),
+            None => Ok(()),
+        }
+    }
 }
 
-#[tokio::main(flavor = "current_thread")]
-async fn main() -> anyhow::Result<()> {
-    let cli = Cli::parse();
-    tracing_subscriber::fmt::init();
+enum TagFilter {
+    Regex(regex::Regex),
+    Glob(glob::Pattern),
+}
 
-    let mut config = read_config(&cli.config)?;
+impl TagFilter {
+    fn from_command(command: &AddCommand) -> anyhow::Result<Option<Self>> {
+        if let Some(filter) = &command.filter {
+            let regex = regex::Regex::new(filter)
+                .with_context(|| format!("Invalid tag filter {filter:?}"))?;
+            return Ok(Some(Self::Regex(regex)));
+        }
 
-    match cli.subcommand {
-        SubCommand::Add(command) => add(&mut config, &command).await?,
-        SubCommand::Pull => pull(&config).await?,
-        SubCommand::Clean => clean(&config).await?,
-        SubCommand::Edit => edit(&mut config).await?,
-        SubCommand::Push(command) => push(&config, &command).await?,
-        SubCommand::Sync(command) => sync(&config, &command).await?,
-    }
+        if let Some(glob) = &command.glob {
+            let pattern =
+                glob::Pattern::new(glob).with_context(|| format!("Invalid tag glob {glob:?}"))?;
+            return Ok(Some(Self::Glob(pattern)));
+        }
 
-    write_config(&cli.config, &config)?;
+        Ok(None)
+    }
 
-    Ok(())
+    fn matches(&self, tag: &str) -> bool {
+        match self {
+            Self::Regex(regex) => regex.is_match(tag),
+            Self::Glob(pattern) => pattern.matches(tag),
+        }
+    }
 }
 
-fn read_config(path: &str) -> anyhow::Result<Config> {
-    let path = std::path::Path::new(path);
-    if !path.exists() {
-        return Ok(Default::default());
+async fn remove(config: &mut Config, command: &RemoveCommand) -> anyhow::Result<()> {
+    if command.all {
+        config.pull_profiles.clear();
+        return Ok(());
     }
 
-    let content = std::fs::read_to_string(path).context("Failed to read config")?;
-    serde_json::from_str(&content).context("Failed to parse config")
-}
+    let repo = command
+        .repo
+        .as_deref()
+        .expect("repo is required without --all");
+    let image = registry_image(
+        command.registry.as_deref(),
+        image_name(command.library.as_ref(), repo),
+    );
 
-fn write_config(path: &str, config: &Config) -> anyhow::Result<()> {
-    let content = serde_json::to_string_pretty(config).context("Failed to serialize config")?;
-    std::fs::write(path, content).context("Failed to write config")
-}
+    let Some(profile) = config.pull_profiles.get_mut(&image) else {
+        anyhow::bail!("No profile for {image} in the config");
+    };
 
-async fn sync(config: &Config, command: &SyncCommand) -> anyhow::Result<()> {
-    pull(&config).await?;
-    push(
-        &config,
-        &PushCommand {
-            registry: command.registry.clone(),
-            clean: command.clean,
-        },
-    )
-    .await?;
+    let Some(tags) = &command.tags else {
+        config.pull_profiles.remove(&image);
+        return Ok(());
+    };
+
+    for tag in tags {
+        if !profile.tags.contains(tag) {
+            anyhow::bail!("Tag {tag} is not configured for {image}");
+        }
+    }
+
+    profile.tags.retain(|tag| !tags.contains(tag));
+    profile.digests.retain(|tag, _| !tags.contains(tag));
+    profile.notes.retain(|tag, _| !tags.contains(tag));
+
+    if profile.tags.is_empty() && profile.pattern.is_none() {
+        config.pull_profiles.remove(&image);
+    }
 
     Ok(())
 }
 
-async fn push(config: &Config, command: &PushCommand) -> anyhow::Result<()> {
-    let mut responses: HashMap<String, FetchTagsResponse> = HashMap::new();
+async fn search(
+    config: &mut Config,
+    hub: &Hub,
+    policy: &Policy,
+    command: &SearchCommand,
+    interactive: bool,
+) -> anyhow::Result<()> {
+    if command.add && !interactive {
+        anyhow::bail!("search --add needs an interactive terminal, use add --repo from scripts");
+    }
 
-    for profile in config.pull_profiles.values() {
-        for tag in &profile.tags {
-            let image = profile.image();
+    let results = docker_pullator::search(hub, &command.query, command.limit).await?;
 
-            let response = if let Some(response) = responses.get(&image) {
-                response.clone()
-            } else {
-                let response = fetch_tags(profile.library.clone(), &profile.repo)
-                    .await
-                    .context("Failed to fetch tags")?;
-                responses.insert(image.clone(), response.clone());
-                response
-            };
+    if results.is_empty() {
+        println!("no images found for {:?}", command.query);
+        return Ok(());
+    }
 
-            let mut targets = vec![format!("{}/{}:{}", command.registry, &image, tag)];
+    if !command.add {
+        let name_width = results
+            .iter()
+            .map(|result| result.repo_name.len())
+            .chain(std::iter::once("NAME".len()))
+            .max()
+            .unwrap_or_default();
+
+        println!("{:name_width$}  {:>6}  DESCRIPTION", "NAME", "STARS");
+        for result in &results {
+            println!(
+                "{:name_width$}  {:>6}  {}",
+                result.repo_name,
+                result.star_count,
+                result.short_description.trim()
+            );
+        }
 
-            let item = response.results.iter().find(|item| &item.name == tag);
-            if let Some(item) = item {
-                targets.extend(
-                    response
-                        .results
-                        .iter()
-                        .filter(|x| x.digest == item.digest)
-                        .map(|item| format!("{}/{}:{}", command.registry, &image, item.name)),
-                );
-            }
+        return Ok(());
+    }
+
+    let names = results
+        .iter()
+        .map(|result| result.repo_name.clone())
+        .collect::<Vec<_>>();
+    let name = Select::new("Please choose an image to add:", names)
+        .prompt()
+        .context("Failed to prompt")?;
+
+    let (library, repo) = match name.split_once('/') {
+        Some((library, repo)) => (Some(library.to_string()), repo.to_string()),
+        None => (None, name),
+    };
 
-            for target in targets {
-                docker_command()
-                    .arg("tag")
-                    .arg(format!("{}:{}", &image, tag))
-                    .arg(&target)
-                    .output()
-                    .context("Failed to tag image")?;
+    add(
+        config,
+        hub,
+        policy,
+        &AddCommand {
+            // An empty library skips the library prompt for official images.
+            library: Some(library.unwrap_or_default()),
+            repo: Some(repo),
+            ..Default::default()
+        },
+        interactive,
+    )
+    .await
+}
 
-                docker_command()
-                    .arg("push")
-                    .arg(&target)
-                    .output()
-                    .context("Failed to push image")?;
+async fn add_repeatedly(
+    config: &mut Config,
+    hub: &Hub,
+    policy: &Policy,
+    command: &AddCommand,
+    interactive: bool,
+) -> anyhow::Result<()> {
+    if !interactive {
+        anyhow::bail!("add --repeat needs an interactive terminal");
+    }
 
-                docker_command()
-                    .arg("image")
-                    .arg("rm")
-                    .arg(&target)
-                    .output()
-                    .context("Failed to remove image")?;
+    let mut command = command.clone();
+
+    loop {
+        // Profiles are only touched once all prompts of an image are answered,
+        // so a cancelled or failed image leaves the earlier ones intact.
+        // Ctrl-C ends the whole command, which saves the images added so far.
+        if let Err(error) = add(config, hub, policy, &command, interactive).await {
+            match error.downcast_ref::<InquireError>() {
+                Some(InquireError::OperationCanceled) => break,
+                Some(InquireError::OperationInterrupted) => return Err(error),
+                _ => eprintln!("Error: {error:?}"),
             }
         }
-    }
 
-    if command.clean {
-        clean(&config).await?;
+        let another = Confirm::new("Add another image?")
+            .with_default(true)
+            .prompt();
+
+        match another {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(InquireError::OperationCanceled) => break,
+            Err(error) => return Err(error).context("Failed to prompt"),
+        }
+
+        command.library = None;
+        command.repo = None;
+        command.tags = None;
+        command.tags_file = None;
+        command.pattern = None;
+        command.image = None;
     }
 
     Ok(())
 }
 
-async fn edit(config: &mut Config) -> anyhow::Result<()> {
-    let profiles = config
-        .pull_profiles
-        .keys()
-        .map(|key| key.clone())
-        .collect::<Vec<_>>();
+/// Docker only accepts lowercase image names, so Foo and foo would end up as the same image.
+fn lowercase_name(name: String) -> String {
+    let lowercase = name.to_lowercase();
+    if lowercase != name {
+        tracing::warn!("Using {lowercase} instead of {name}, image names are lowercase");
+    }
 
-    let image = Select::new("Please choose profile to edit:", profiles)
-        .prompt()
-        .context("Failed to prompt")?;
+    lowercase
+}
 
-    let mut profile = config
-        .pull_profiles
-        .get(&image)
-        .expect("profile should exist")
-        .clone();
+fn read_tags_file(path: &std::path::Path) -> anyhow::Result<Vec<String>> {
+    let content = if path == std::path::Path::new("-") {
+        std::io::read_to_string(std::io::stdin()).context("Failed to read tags from stdin")?
+    } else {
+        std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read tags from {}", path.display()))?
+    };
 
-    let tags = profile.tags.iter().cloned().collect::<Vec<_>>();
+    Ok(content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(String::from)
+        .collect())
+}
 
-    let tags = MultiSelect::new("Please choose tags to keep:", tags)
-        .with_all_selected_by_default()
-        .prompt()
-        .context("Failed to prompt")?;
+/// Adds the references of a manifest file, checking all of them before changing the config.
+async fn add_from_file(
+    config: &mut Config,
+    hub: &Hub,
+    policy: &Policy,
+    command: &AddCommand,
+) -> anyhow::Result<()> {
+    let path = command
+        .from_file
+        .as_deref()
+        .context("--from-file is required")?;
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+
+    let mut images = BTreeMap::<_, Vec<String>>::new();
+    let mut problems = vec![];
+    for (number, line) in content.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
 
-    profile.tags = tags.into_iter().collect();
+        match parse_image_reference(line) {
+            Ok(ImageReference { tag: None, .. }) => {
+                problems.push(format!("line {}: {line} has no tag", number + 1))
+            }
+            Ok(ImageReference {
+                registry,
+                library,
+                repo,
+                tag: Some(tag),
+            }) => {
+                let tags = images
+                    .entry((
+                        registry,
+                        library.map(|library| library.to_lowercase()),
+                        repo.to_lowercase(),
+                    ))
+                    .or_default();
+                if !tags.contains(&tag) {
+                    tags.push(tag);
+                }
+            }
+            Err(error) => problems.push(format!("line {}: {error}", number + 1)),
+        }
+    }
 
-    if profile.tags.is_empty() {
-        config.pull_profiles.remove(&image);
-    } else {
-        config.pull_profiles.insert(image, profile);
+    if !problems.is_empty() {
+        anyhow::bail!(
+            "Invalid references in {}:\n  {}",
+            path.display(),
+            problems.join("\n  ")
+        );
+    }
+
+    if !command.no_verify {
+        let token = hub_token(hub, config.auth.as_ref()).await?;
+        let mut missing = vec![];
+
+        for ((registry, library, repo), tags) in &images {
+            let name = registry_image(registry.as_deref(), image_name(library.as_ref(), repo));
+            match fetch_tags(
+                hub,
+                registry.as_deref(),
+                library.as_ref(),
+                repo,
+                command.fetch,
+                token.as_deref(),
+            )
+            .await
+            {
+                Ok(response) => missing.extend(
+                    tags.iter()
+                        .filter(|tag| !response.results.iter().any(|item| &item.name == *tag))
+                        .map(|tag| format!("{name}:{tag}")),
+                ),
+                Err(error) => missing.push(format!("{name}: {error:#}")),
+            }
+        }
+
+        if !missing.is_empty() {
+            anyhow::bail!(
+                "References not found, use --no-verify to add them anyway:\n  {}",
+                missing.join("\n  ")
+            );
+        }
+    }
+
+    for ((registry, library, repo), tags) in images {
+        add(
+            config,
+            hub,
+            policy,
+            &AddCommand {
+                registry,
+                // An empty library skips the library prompt for official images.
+                library: Some(library.unwrap_or_default()),
+                repo: Some(repo),
+                tags: Some(tags),
+                from_file: None,
+                ..command.clone()
+            },
+            false,
+        )
+        .await?;
     }
 
     Ok(())
 }
 
-async fn add(config: &mut Config, command: &AddCommand) -> anyhow::Result<()> {
+async fn add(
+    config: &mut Config,
+    hub: &Hub,
+    policy: &Policy,
+    command: &AddCommand,
+    interactive: bool,
+) -> anyhow::Result<()> {
+    let mut command = command.clone();
+    if let Some(image) = command.image.take() {
+        command.registry = image.registry;
+        command.library = Some(image.library.unwrap_or_default());
+        command.repo = Some(image.repo);
+        if let Some(tag) = image.tag {
+            command.tags.get_or_insert_with(Vec::new).push(tag);
+        }
+    }
+    let command = &command;
+
+    let filter = TagFilter::from_command(command)?;
+    let ignore = command
+        .ignore
+        .iter()
+        .map(|pattern| {
+            glob::Pattern::new(pattern)
+                .with_context(|| format!("Invalid ignore pattern {pattern:?}"))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
     let library = if let Some(library) = &command.library {
         library.clone()
+    } else if !interactive {
+        String::new()
     } else {
         Text::new("Library:")
             .with_help_message("empty for _")
@@ -232,156 +2896,449 @@ async fn add(config: &mut Config, command: &AddCommand) -> anyhow::Result<()> {
     let library = if library.is_empty() {
         None
     } else {
-        Some(library)
+        Some(lowercase_name(library))
     };
 
+    let registry = command.registry.clone();
+
     let repo = if let Some(repo) = &command.repo {
         repo.clone()
+    } else if !interactive {
+        anyhow::bail!("--repo is required when not running interactively");
     } else {
         Text::new("Repo:").prompt().context("Failed to prompt")?
     };
+    let repo = lowercase_name(repo);
+    policy.check(registry.as_deref(), library.as_deref(), &repo)?;
+
+    let file_tags = command
+        .tags_file
+        .as_deref()
+        .map(read_tags_file)
+        .transpose()?;
+
+    if let Some(pattern) = &command.pattern {
+        regex::Regex::new(pattern).with_context(|| format!("Invalid pattern {pattern:?}"))?;
+    }
+
+    if command.tags.is_none()
+        && file_tags.is_none()
+        && command.pattern.is_none()
+        && command.latest.is_none()
+        && filter.is_none()
+        && command.since.is_none()
+        && command.until.is_none()
+        && !interactive
+    {
+        anyhow::bail!("--tags or a filter is required when not running interactively");
+    }
+
+    // Browsing in pages only fetches the tags of the first page up front.
+    let browse = command.page.filter(|_| {
+        interactive
+            && command.tags.is_none()
+            && file_tags.is_none()
+            && command.pattern.is_none()
+            && command.latest.is_none()
+    });
+    let fetch = match browse {
+        Some(size) => FetchOptions {
+            max_pages: Some(size.div_ceil(100)),
+            max_tags: None,
+            page_size: Some(size.min(100)),
+            partial_ok: command.fetch.partial_ok,
+        },
+        None => command.fetch,
+    };
 
-    let mut response = fetch_tags(library.as_ref(), &repo).await?;
+    let token = hub_token(hub, config.auth.as_ref()).await?;
+    let mut response = fetch_tags(
+        hub,
+        registry.as_deref(),
+        library.as_ref(),
+        &repo,
+        fetch,
+        token.as_deref(),
+    )
+    .await?;
 
     let tags = if let Some(tags) = &command.tags {
         tags.clone()
+    } else if let Some(tags) = file_tags {
+        if !command.no_verify {
+            let missing = tags
+                .iter()
+                .filter(|tag| !response.results.iter().any(|item| &item.name == *tag))
+                .cloned()
+                .collect::<Vec<_>>();
+
+            if !missing.is_empty() {
+                anyhow::bail!(
+                    "Tags of {repo} not found: {}, use --no-verify to add them anyway",
+                    missing.join(", ")
+                );
+            }
+        }
+
+        tags
+    } else if command.pattern.is_some() {
+        vec![]
     } else {
-        response.results.sort_by(|a, b| b.name.cmp(&a.name));
+        command.sort.sort(&mut response.results);
+
+        let offer = |item: &FetchTagsItem| {
+            if !item.matches_platform(command.os.as_deref(), &command.arch)
+                || filter
+                    .as_ref()
+                    .is_some_and(|filter| !filter.matches(&item.name))
+                || ignore.iter().any(|pattern| pattern.matches(&item.name))
+            {
+                return false;
+            }
+
+            if command.since.is_none() && command.until.is_none() {
+                return true;
+            }
 
+            item.published()
+                .map_or(command.include_undated, |published| {
+                    command.since.is_none_or(|since| published >= since)
+                        && command.until.is_none_or(|until| published < until)
+                })
+        };
         let tags = response
             .results
             .iter()
+            .filter(|item| offer(item))
             .map(|item| item.name.clone())
             .collect::<Vec<_>>();
 
-        MultiSelect::new("Please choose wanted images:", tags)
-            .prompt()
-            .context("Failed to prompt")?
+        if let Some(count) = command.latest {
+            let tags = latest_tags(&tags, count, command.suffix.as_deref());
+            if tags.is_empty() {
+                anyhow::bail!("No version tags of {repo} found for --latest");
+            }
+
+            tags
+        } else if !interactive {
+            if tags.is_empty() {
+                anyhow::bail!("No tags of {repo} match the filter");
+            }
+
+            tags
+        } else if let Some(size) = browse {
+            let pages = TagPages {
+                source: (registry.as_deref(), library.as_ref(), repo.as_str()),
+                fetch,
+                token: token.as_deref(),
+                size,
+                sort: command.sort,
+            };
+            browse_tags(hub, &pages, &mut response, tags, &offer).await?
+        } else {
+            let choices = tags
+                .into_iter()
+                .map(|tag| TagChoice::new(tag, &response))
+                .collect();
+
+            MultiSelect::new("Please choose wanted images:", choices)
+                .prompt()
+                .context("Failed to prompt")?
+                .into_iter()
+                .map(|choice| choice.name)
+                .collect()
+        }
     };
 
+    if tags.is_empty() && command.pattern.is_none() {
+        eprintln!("No tags selected, leaving the config unchanged");
+        return Ok(());
+    }
+
     let profile = config
         .pull_profiles
-        .entry(image_name(library.as_ref(), &repo))
+        .entry(registry_image(
+            registry.as_deref(),
+            image_name(library.as_ref(), &repo),
+        ))
         .or_insert_with(|| PullProfile {
+            registry,
             library,
             repo,
             tags: vec![],
+            pattern: None,
+            platforms: vec![],
+            digests: BTreeMap::new(),
+            ignore: vec![],
+            notes: BTreeMap::new(),
         });
 
-    profile.tags.extend(tags);
+    for pattern in &command.ignore {
+        if !profile.ignore.contains(pattern) {
+            profile.ignore.push(pattern.clone());
+        }
+    }
 
-    Ok(())
-}
+    if let Some(pattern) = &command.pattern {
+        let regex = regex::Regex::new(pattern).context("Failed to compile pattern")?;
+        let matching = response
+            .results
+            .iter()
+            .filter(|item| regex.is_match(&item.name) && !profile.is_ignored(&item.name))
+            .count();
+        if matching == 0 {
+            tracing::warn!("No tags of {} match {pattern} yet", profile.image());
+        }
+        profile.pattern = Some(pattern.clone());
+    }
 
-async fn clean(config: &Config) -> anyhow::Result<()> {
-    for profile in config.pull_profiles.values() {
-        let image = profile.image();
+    for tag in &tags {
+        if command.pin {
+            let digest = response
+                .results
+                .iter()
+                .find(|item| &item.name == tag)
+                .and_then(|item| item.digest.clone())
+                .with_context(|| format!("No digest found to pin tag {tag}"))?;
+            profile.digests.insert(tag.clone(), digest);
+        }
+
+        if command.all_platforms {
+            let platforms = response
+                .results
+                .iter()
+                .filter(|item| &item.name == tag)
+                .flat_map(|item| &item.images)
+                .filter(|image| image.matches(command.os.as_deref(), &command.arch))
+                .filter_map(FetchTagsImageItem::platform);
+
+            for platform in platforms {
+                if !profile.platforms.contains(&platform) {
+                    profile.platforms.push(platform);
+                }
+            }
+        }
+    }
 
-        for tag in &profile.tags {
-            docker_command()
-                .arg("image")
-                .arg("rm")
-                .arg(&format!("{}:{}", image, tag))
-                .status()
-                .context("Failed to remove image")?;
+    // The tags picked now only stand in for what the directive selects at pull time.
+    let tags = match command.latest {
+        Some(count) if command.track => vec![LatestDirective {
+            count,
+            suffix: command.suffix.clone(),
+        }
+        .to_string()],
+        _ if command.as_digest => tags
+            .iter()
+            .map(|tag| {
+                response
+                    .results
+                    .iter()
+                    .find(|item| &item.name == tag)
+                    .and_then(|item| item.digest.clone())
+                    .filter(|digest| is_digest(digest))
+                    .with_context(|| format!("No digest found for tag {tag}"))
+            })
+            .collect::<anyhow::Result<_>>()?,
+        _ => tags,
+    };
+
+    for tag in tags {
+        if let Some(note) = &command.note {
+            profile.notes.insert(tag.clone(), note.clone());
+        }
+
+        profile.add_tag(tag);
+    }
+
+    if !command.all_platforms {
+        let os = command.os.as_deref().unwrap_or("linux");
+        for arch in &command.arch {
+            let platform = format!("{os}/{arch}");
+            if !profile.platforms.contains(&platform) {
+                profile.platforms.push(platform);
+            }
         }
     }
 
     Ok(())
 }
 
-fn docker_command() -> Command {
-    let mut command = Command::new("docker");
-    command.stdout(Stdio::inherit());
-    command.stderr(Stdio::inherit());
+fn confirm_removal(count: usize, yes: bool, interactive: bool) -> anyhow::Result<bool> {
+    if yes || count == 0 {
+        return Ok(true);
+    }
+
+    if !interactive {
+        anyhow::bail!(
+            "Refusing to remove {count} image(s) without confirmation, pass --yes to remove them"
+        );
+    }
 
-    command
+    Confirm::new(&format!("Remove {count} local image(s)?"))
+        .with_default(false)
+        .prompt()
+        .context("Failed to prompt")
 }
 
-async fn pull(config: &Config) -> anyhow::Result<()> {
+async fn prune(
+    config: &Config,
+    docker: &Docker,
+    command: &PruneCommand,
+    interactive: bool,
+) -> anyhow::Result<()> {
+    let mut configured = BTreeMap::<String, BTreeSet<&str>>::new();
     for profile in config.pull_profiles.values() {
-        for tag in &profile.tags {
-            let image = profile.image();
+        configured
+            .entry(profile.image())
+            .or_default()
+            .extend(profile.tags.iter().map(String::as_str));
+    }
+
+    let mut stale = vec![];
+    for (image, tags) in &configured {
+        stale.extend(
+            local_tags(docker, image)
+                .await?
+                .into_iter()
+                .filter(|tag| !tags.contains(tag.as_str()))
+                .map(|tag| format!("{image}:{tag}")),
+        );
+    }
 
-            let status = docker_command()
-                .arg("pull")
-                .arg(&format!("{}:{}", image, tag))
-                .status()
-                .context("Failed to pull image")?;
+    if stale.is_empty() {
+        println!("nothing to prune");
+        return Ok(());
+    }
 
-            if !status.success() {
-                anyhow::bail!("Pull failed with status: {status}");
-            }
+    for reference in &stale {
+        println!("{reference}");
+    }
+
+    if !docker.is_dry_run() && !confirm_removal(stale.len(), command.yes, interactive)? {
+        return Ok(());
+    }
+
+    for reference in &stale {
+        let output = docker
+            .command()
+            .arg("image")
+            .arg("rm")
+            .arg(reference)
+            .output()
+            .await
+            .context("Failed to remove image")?;
+
+        if !output.status.success() && !is_missing_image(&output) {
+            return Err(docker_error(&format!("Removing {reference}"), &output));
         }
     }
 
     Ok(())
 }
 
-fn image_name(library: Option<impl AsRef<str>>, repo: &str) -> String {
-    if let Some(library) = library {
-        format!("{}/{}", library.as_ref(), repo)
-    } else {
-        repo.to_string()
+/// Parses an ISO date or timestamp, or a duration like 30d meaning that long ago.
+fn parse_date(value: &str) -> Result<SystemTime, String> {
+    if let Ok(ago) = humantime::parse_duration(value) {
+        return SystemTime::now()
+            .checked_sub(ago)
+            .ok_or_else(|| format!("{value} ago is out of range"));
     }
-}
 
-async fn fetch_tags(
-    library: Option<impl AsRef<str>>,
-    repo: &str,
-) -> anyhow::Result<FetchTagsResponse> {
-    let library = library.as_ref().map(|s| s.as_ref());
+    let timestamp = if value.contains('T') {
+        value.trim_end_matches('Z').to_string()
+    } else {
+        format!("{value}T00:00:00")
+    };
 
-    let url = format!(
-        "https://hub.docker.com/v2/repositories/{}/{}/tags?page_size=100",
-        library.unwrap_or("library"),
-        repo
-    );
+    humantime::parse_rfc3339_weak(&timestamp).map_err(|_| {
+        format!("expected a date like 2024-01-31 or a duration like 30d, got {value:?}")
+    })
+}
 
-    tracing::trace!("fetch_tags URL: {url}");
+/// Parses `SRC=DST` of push --map, where the new name has to be a plain tag.
+fn parse_tag_mapping(value: &str) -> Result<(String, String), String> {
+    let (source, target) = value
+        .split_once('=')
+        .ok_or_else(|| format!("invalid mapping {value:?}, expected SRC=DST like 15.4=prod"))?;
 
-    let image = image_name(library, repo);
+    if source.is_empty() || target.is_empty() || target.contains([':', '/', '@']) {
+        return Err(format!(
+            "invalid mapping {value:?}, expected SRC=DST like 15.4=prod"
+        ));
+    }
 
-    reqwest::get(url)
-        .await
-        .with_context(|| format!("Failed to fetch tags for {image}"))?
-        .json()
-        .await
-        .with_context(|| format!("Failed to parse response for {image}"))
+    Ok((source.to_string(), target.to_string()))
 }
 
-#[derive(Debug, Default, Clone, Serialize, Deserialize)]
-struct Config {
-    pull_profiles: BTreeMap<String, PullProfile>,
-}
+/// Expands `$VAR` and `${VAR}` from the environment, `$$` is a literal `$`.
+fn expand_env(value: &str) -> Result<String, String> {
+    let mut expanded = String::new();
+    let mut chars = value.chars().peekable();
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash)]
-struct PullProfile {
-    library: Option<String>,
-    repo: String,
-    tags: Vec<String>,
-}
+    while let Some(char) = chars.next() {
+        if char != '$' {
+            expanded.push(char);
+            continue;
+        }
 
-impl PullProfile {
-    fn image(&self) -> String {
-        image_name(self.library.as_ref(), &self.repo)
-    }
-}
+        let name = match chars.peek() {
+            Some('$') => {
+                chars.next();
+                expanded.push('$');
+                continue;
+            }
+            Some('{') => {
+                chars.next();
+                let mut name = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(char) => name.push(char),
+                        None => return Err(format!("unclosed ${{ in {value:?}")),
+                    }
+                }
+
+                if name.is_empty() {
+                    return Err(format!("empty ${{}} in {value:?}"));
+                }
+
+                name
+            }
+            _ => {
+                let mut name = String::new();
+                while let Some(&char) = chars.peek() {
+                    if !char.is_ascii_alphanumeric() && char != '_' {
+                        break;
+                    }
+
+                    name.push(char);
+                    chars.next();
+                }
+
+                name
+            }
+        };
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct FetchTagsResponse {
-    results: Vec<FetchTagsItem>,
-}
+        // A $ that doesn't start a name is kept as is.
+        if name.is_empty() {
+            expanded.push('$');
+            continue;
+        }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct FetchTagsItem {
-    name: String,
-    images: Vec<FetchTagsImageItem>,
-    digest: Option<String>,
+        let value =
+            std::env::var(&name).map_err(|_| format!("environment variable {name} is not set"))?;
+        expanded.push_str(&value);
+    }
+
+    Ok(expanded)
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct FetchTagsImageItem {
-    os: String,
-    architecture: String,
+#[derive(Debug, Clone, Serialize)]
+struct ListItem {
+    image: String,
+    tags: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pattern: Option<String>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    notes: BTreeMap<String, String>,
 }